@@ -0,0 +1,184 @@
+use core::*;
+use epd::*;
+use eval::*;
+use game::*;
+use moves::*;
+use san::*;
+use search::*;
+use zobrist::*;
+
+use std::cmp::max;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Human,
+    // one JSON object per line
+    Json,
+    // a single EPD record with the final result
+    Epd
+}
+
+// the result of one iteration of the search
+#[derive(Debug, PartialEq, Clone)]
+pub struct AnalysisLine {
+    pub depth: u8,
+    pub score: Score,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    pub time_ms: u64
+}
+
+impl AnalysisLine {
+    // the PV is written in SAN, so needs the position it starts from
+    pub fn to_human_str(&self, game: &Game) -> String {
+        let score_str = match self.score.mate_in() {
+            Some(moves) if moves > 0 => format!("mate in {}", moves),
+            Some(moves) => format!("mated in {}", -moves),
+            None => format!("{:+.2}", self.score.unwrap() as f32 / 100.0)
+        };
+
+        format!("depth {:>2}  score {:>10}  nodes {:>10}  time {:>7} ms  pv {}",
+                self.depth, score_str, self.nodes, self.time_ms, moves_to_san(game, &self.pv).join(" "))
+    }
+
+    pub fn to_json_str(&self) -> String {
+        let score_str = match self.score.mate_in() {
+            Some(moves) => format!("{{\"mate\":{}}}", moves),
+            None => format!("{{\"cp\":{}}}", self.score.unwrap())
+        };
+
+        let pv_str: Vec<String> = self.pv.iter().map(|m| format!("\"{}\"", m.to_uci_str())).collect();
+
+        format!("{{\"depth\":{},\"score\":{},\"nodes\":{},\"time_ms\":{},\"pv\":[{}]}}",
+                self.depth, score_str, self.nodes, self.time_ms, pv_str.join(","))
+    }
+
+    // the analysis opcodes of EPD: ce (or dm for a mate), acd, acn, acs and pv
+    pub fn to_epd_entry(&self, game: Game) -> EpdEntry {
+        let mut entry = EpdEntry::new(game);
+
+        if let Some(&m) = self.pv.first() {
+            entry.set_operation("bm", vec![EpdOperand::Move(m)]);
+        }
+
+        match self.score.mate_in() {
+            Some(moves) => entry.set_operation("dm", vec![EpdOperand::Integer(moves as i64)]),
+            None => entry.set_operation("ce", vec![EpdOperand::Integer(self.score.unwrap() as i64)])
+        }
+
+        entry.set_operation("acd", vec![EpdOperand::Integer(self.depth as i64)]);
+        entry.set_operation("acn", vec![EpdOperand::Integer(self.nodes as i64)]);
+        entry.set_operation("acs", vec![EpdOperand::Integer((self.time_ms / 1000) as i64)]);
+        entry.set_operation("pv", self.pv.iter().map(|m| EpdOperand::Move(*m)).collect());
+
+        entry
+    }
+}
+
+// Iterative deepening on a single position until the depth, movetime or node
+// limit is reached, printing each completed iteration. Scores are from the
+// point of view of the side to move.
+pub fn analyze(game: Game, limits: SearchLimits, hash_mb: usize, format: OutputFormat) -> Vec<AnalysisLine> {
+    let abort = Arc::new(AtomicBool::new(false));
+    let mut context = SearchContext::new(game, TranspositionTable::with_size_mb(hash_mb), abort);
+
+    context.timer = SearchTimer::new(limits.movetime.unwrap_or(u32::max_value()));
+    context.limits = limits;
+
+    let mut lines = Vec::new();
+
+    let result = iterative_deepening(&mut context, |context, depth, score, _| {
+        let pv = context.table.get_pv(game, depth as usize)
+            .iter()
+            .map(|entry| entry.best_move())
+            .collect();
+
+        let line = AnalysisLine {
+            depth: depth,
+            score: score,
+            pv: pv,
+            nodes: context.nodes,
+            time_ms: context.timer.elapsed_ms()
+        };
+
+        match format {
+            OutputFormat::Human => println!("{}", line.to_human_str(&game)),
+            OutputFormat::Json => println!("{}", line.to_json_str()),
+            OutputFormat::Epd => {}
+        }
+
+        lines.push(line);
+        true
+    });
+
+    let best_move = if result.best_move.is_null() { None } else { Some(result.best_move) };
+
+    let nps = context.nodes * 1000 / max(context.timer.elapsed_ms(), 1);
+
+    match format {
+        OutputFormat::Human => {
+            let best_move_str = best_move.map(|m| move_to_san(&game, m)).unwrap_or("(none)".to_string());
+            println!("best move {}  ({} nodes, {} nps)", best_move_str, context.nodes, nps);
+        },
+        OutputFormat::Json => {
+            let best_move_str = best_move.map(|m| m.to_uci_str()).unwrap_or("(none)".to_string());
+            println!("{{\"bestmove\":\"{}\",\"nodes\":{},\"nps\":{}}}", best_move_str, context.nodes, nps);
+        },
+        OutputFormat::Epd => {
+            let entry = match lines.last() {
+                Some(line) => line.to_epd_entry(game),
+                None => EpdEntry::new(game)
+            };
+            println!("{}", entry.to_epd_string());
+        }
+    }
+
+    return lines;
+}
+
+#[cfg(test)]
+mod test {
+    use analyze::*;
+
+    #[test]
+    fn respects_limits() {
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+
+        let mut limits = SearchLimits::new();
+        limits.depth = Some(3);
+        let lines = analyze(game, limits, 1, OutputFormat::Json);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.iter().map(|l| l.depth).collect::<Vec<u8>>(), vec![1, 2, 3]);
+
+        let mut limits = SearchLimits::new();
+        limits.nodes = Some(1000);
+        let lines = analyze(game, limits, 1, OutputFormat::Human);
+        assert!(lines.last().unwrap().nodes <= 1000);
+    }
+
+    #[test]
+    fn output_formats() {
+        let game = Game::starting_position();
+        let e4 = move_from_san(&game, "e4").unwrap();
+
+        let line = AnalysisLine {
+            depth: 2,
+            score: Score::new(-35),
+            pv: vec![e4],
+            nodes: 100,
+            time_ms: 3
+        };
+
+        assert_eq!(line.to_json_str(), "{\"depth\":2,\"score\":{\"cp\":-35},\"nodes\":100,\"time_ms\":3,\"pv\":[\"e2e4\"]}");
+        assert!(line.to_human_str(&game).contains("-0.35"));
+        assert!(line.to_human_str(&game).ends_with("pv e4"));
+
+        let epd = line.to_epd_entry(game).to_epd_string();
+        assert_eq!(epd, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; ce -35; acd 2; acn 100; acs 0; pv e4;");
+        assert_eq!(parse_epd(&epd).unwrap().best_moves(), vec![e4]);
+    }
+}
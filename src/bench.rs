@@ -0,0 +1,100 @@
+use core::*;
+use eval::*;
+use game::*;
+use search::*;
+use zobrist::*;
+
+use std::cmp::max;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+pub const DEFAULT_BENCH_DEPTH: u8 = 6;
+
+// the table size changes which entries collide, and so the node count
+const BENCH_HASH_MB: usize = 16;
+
+const BENCH_POSITIONS: [&'static str; 12] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+    "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+    "rq3rk1/ppp2ppp/1bnpb3/3N2B1/3NP3/7P/PPPQ1PP1/2KR3R w - - 7 14",
+    "r1bq1r1k/1pp1n1pp/1p1p4/4p2Q/4Pp2/1BNP4/PPP2PPP/3R1RK1 w - - 2 14",
+    "r3r1k1/2p2ppp/p1p1bn2/8/1q2P3/2NPQN2/PPP3PP/R4RK1 b - - 2 15",
+    "r1bbk1nr/pp3p1p/2n5/1N4p1/2Np1B2/8/PPP2PPP/2KR1B1R w kq - 0 13",
+    "r1bq1rk1/ppp1nppp/4n3/3p3Q/3P4/1BP1B3/PP1N2PP/R4RK1 w - - 1 16",
+    "4r1k1/r1q2ppp/ppp2n2/4P3/5Rb1/1N1BQ3/PPP3PP/R5K1 w - - 1 17",
+    "2rqkb1r/ppp2p2/2npb1p1/1N1Nn2p/2P1PP2/8/PP2B1PP/R1BQK2R b KQ - 0 11",
+    "6k1/6p1/6Pp/ppp5/3pn2P/1P3K2/1PP2P2/8 b - - 3 54"
+];
+
+pub struct BenchResult {
+    pub nodes: u64,
+    pub time_ms: u64
+}
+
+// Searches every position to the given depth from an empty table. With no
+// time limit the node count only changes when the search itself does.
+pub fn run_bench(depth: u8, verbose: bool) -> BenchResult {
+    init_zobrist_hashing();
+
+    let depth = max(depth, 1);
+    let start_game = Game::starting_position();
+    let abort = Arc::new(AtomicBool::new(false));
+    let mut context = SearchContext::new(start_game, TranspositionTable::with_size_mb(BENCH_HASH_MB), abort);
+
+    let mut limits = SearchLimits::new();
+    limits.depth = Some(depth);
+
+    let mut total_nodes = 0;
+    let timer = SearchTimer::new(u32::max_value());
+
+    for (idx, fen) in BENCH_POSITIONS.iter().enumerate() {
+        let game = Game::from_fen_str(fen).unwrap();
+
+        context.new_game();
+        context.tree.reset_root(game, Vec::new());
+        context.limits = limits.clone();
+        context.timer = SearchTimer::new(u32::max_value());
+
+        iterative_deepening(&mut context, |_, _, _, _| true);
+
+        if verbose {
+            println!("Position {:>2}/{}: {:>10} nodes  {}", idx + 1, BENCH_POSITIONS.len(), context.nodes, fen);
+        }
+
+        total_nodes += context.nodes;
+    }
+
+    BenchResult {
+        nodes: total_nodes,
+        time_ms: timer.elapsed_ms()
+    }
+}
+
+pub fn bench(depth: u8) {
+    let result = run_bench(depth, true);
+
+    println!("");
+    println!("Depth: {}", depth);
+    println!("Total nodes: {}", result.nodes);
+    println!("Time (ms): {}", result.time_ms);
+    println!("Nodes/second: {}", result.nodes * 1000 / max(result.time_ms, 1));
+}
+
+#[cfg(test)]
+mod test {
+    use bench::*;
+
+    // Any change to the search or move ordering should change this on purpose.
+    const BENCH_DEPTH_3_NODES: u64 = 88575;
+
+    #[test]
+    fn deterministic() {
+        let first = run_bench(3, false);
+        let second = run_bench(3, false);
+
+        assert_eq!(first.nodes, BENCH_DEPTH_3_NODES);
+        assert_eq!(first.nodes, second.nodes);
+    }
+}
@@ -80,6 +80,12 @@ impl Board {
         return !self.occupied();
     }
 
+    // anything besides the king and pawns
+    pub fn has_non_pawn_material(&self, color: Color) -> bool {
+        let king_and_pawns = self.get_pieces(color, PieceType::Pawn) | self.get_pieces(color, PieceType::King);
+        return self.occupied_by(color) != king_and_pawns;
+    }
+
     pub fn color_at(&self, sq: Square) -> Option<Color> {
         let bit = sq.bitrep();
 
@@ -32,6 +32,15 @@ impl SearchTimer {
     pub fn finished(&self) -> bool {
         Utc::now().timestamp_millis() - self.start_time > self.duration_ms
     }
+
+    // keeps the time already elapsed, but only allows `remaining` more milliseconds
+    pub fn set_remaining(&mut self, remaining: u32) {
+        self.duration_ms = Utc::now().timestamp_millis() - self.start_time + remaining as i64;
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        (Utc::now().timestamp_millis() - self.start_time) as u64
+    }
 }
 
 pub struct Counter(i64);
@@ -61,9 +70,8 @@ impl Square {
     pub fn file(self) -> u32 { return 8 - self.0 % 8; }
 
     pub fn from_rank_file(rank: u32, file: u32) -> Option<Square> {
-        let idx = (rank - 1) * 8 + file;
-        if idx < 64 {
-            return Some(Square::new(idx));
+        if rank >= 1 && rank <= 8 && file < 8 {
+            return Some(Square::new((rank - 1) * 8 + file));
         } else {
             return None;
         }
@@ -0,0 +1,440 @@
+use game::*;
+use moves::*;
+use san::*;
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EpdError {
+    InvalidFen(String),
+    InvalidOperation(String),
+    IllegalMove { opcode: String, san: String },
+    InvalidNumber { opcode: String, value: String }
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EpdError::InvalidFen(ref fen) => write!(f, "invalid position: {}", fen),
+            EpdError::InvalidOperation(ref op) => write!(f, "invalid operation: {}", op),
+            EpdError::IllegalMove { ref opcode, ref san } => write!(f, "illegal move in {}: {}", opcode, san),
+            EpdError::InvalidNumber { ref opcode, ref value } => write!(f, "invalid number in {}: {}", opcode, value)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EpdOperand {
+    Move(Move),
+    Integer(i64),
+    // a quoted string
+    Str(String),
+    // any other unquoted token
+    Symbol(String)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EpdOperation {
+    pub opcode: String,
+    pub operands: Vec<EpdOperand>
+}
+
+// opcodes whose operands are all moves from the EPD position
+fn is_move_opcode(opcode: &str) -> bool {
+    match opcode {
+        "bm" | "am" | "pm" | "sm" => true,
+        _ => false
+    }
+}
+
+// opcodes with integer operands, including the perft counts D1, D2, ...
+fn is_integer_opcode(opcode: &str) -> bool {
+    match opcode {
+        "ce" | "acd" | "acn" | "acs" | "dm" | "fmvn" | "hmvc" | "rc" => true,
+        _ => perft_depth(opcode).is_some()
+    }
+}
+
+// the values an integer opcode can take, so that counters can't wrap around
+fn integer_range(opcode: &str) -> (i64, i64) {
+    match opcode {
+        "hmvc" => (0, u8::max_value() as i64),
+        "fmvn" => (1, u16::max_value() as i64),
+        "acd" | "acn" | "acs" | "rc" => (0, i64::max_value()),
+        _ if perft_depth(opcode).is_some() => (0, i64::max_value()),
+        _ => (i64::min_value(), i64::max_value())
+    }
+}
+
+fn perft_depth(opcode: &str) -> Option<usize> {
+    if opcode.starts_with('D') {
+        opcode[1..].parse::<usize>().ok()
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EpdEntry {
+    pub game: Game,
+    pub operations: Vec<EpdOperation>
+}
+
+impl EpdEntry {
+    pub fn new(game: Game) -> EpdEntry {
+        EpdEntry {
+            game: game,
+            operations: Vec::new()
+        }
+    }
+
+    pub fn operation(&self, opcode: &str) -> Option<&EpdOperation> {
+        self.operations.iter().find(|op| op.opcode == opcode)
+    }
+
+    // replaces any earlier operation with the same opcode
+    pub fn set_operation(&mut self, opcode: &str, operands: Vec<EpdOperand>) {
+        let op = EpdOperation { opcode: opcode.to_string(), operands: operands };
+
+        match self.operations.iter().position(|o| o.opcode == opcode) {
+            Some(idx) => self.operations[idx] = op,
+            None => self.operations.push(op)
+        }
+    }
+
+    fn moves(&self, opcode: &str) -> Vec<Move> {
+        match self.operation(opcode) {
+            Some(op) => op.operands.iter().filter_map(|operand| match *operand {
+                EpdOperand::Move(m) => Some(m),
+                _ => None
+            }).collect(),
+            None => Vec::new()
+        }
+    }
+
+    fn first_integer(&self, opcode: &str) -> Option<i64> {
+        match self.operation(opcode).and_then(|op| op.operands.first()) {
+            Some(&EpdOperand::Integer(x)) => Some(x),
+            _ => None
+        }
+    }
+
+    fn first_string(&self, opcode: &str) -> Option<&str> {
+        match self.operation(opcode).and_then(|op| op.operands.first()) {
+            Some(&EpdOperand::Str(ref s)) => Some(s),
+            Some(&EpdOperand::Symbol(ref s)) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn best_moves(&self) -> Vec<Move> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> Vec<Move> {
+        self.moves("am")
+    }
+
+    pub fn pv(&self) -> Vec<Move> {
+        self.moves("pv")
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.first_string("id")
+    }
+
+    // c0 to c9
+    pub fn comment(&self, idx: usize) -> Option<&str> {
+        self.first_string(&format!("c{}", idx))
+    }
+
+    // centipawn evaluation, from the point of view of the side to move
+    pub fn centipawn_eval(&self) -> Option<i64> {
+        self.first_integer("ce")
+    }
+
+    // (depth, nodes) pairs from the D1, D2, ... operations
+    pub fn perft_counts(&self) -> Vec<(usize, usize)> {
+        self.operations.iter().filter_map(|op| {
+            match (perft_depth(&op.opcode), op.operands.first()) {
+                (Some(depth), Some(&EpdOperand::Integer(nodes))) if nodes >= 0 => Some((depth, nodes as usize)),
+                _ => None
+            }
+        }).collect()
+    }
+
+    pub fn to_epd_string(&self) -> String {
+        // only the first four fields of the FEN, counters are operations in EPD
+        let fen = self.game.to_fen();
+        let fields: Vec<&str> = fen.split_whitespace().take(4).collect();
+        let mut epd = fields.join(" ");
+
+        for op in self.operations.iter() {
+            epd.push(' ');
+            epd.push_str(&op.opcode);
+
+            // the principal variation is played out from the position, the other moves aren't
+            let mut game = self.game;
+
+            for operand in op.operands.iter() {
+                epd.push(' ');
+                match *operand {
+                    EpdOperand::Move(m) => {
+                        epd.push_str(&move_to_san(&game, m));
+                        if op.opcode == "pv" {
+                            game.make_move(m);
+                        }
+                    },
+                    EpdOperand::Integer(x) => epd.push_str(&x.to_string()),
+                    EpdOperand::Str(ref s) => epd.push_str(&format!("\"{}\"", s)),
+                    EpdOperand::Symbol(ref s) => epd.push_str(s)
+                }
+            }
+
+            epd.push(';');
+        }
+
+        epd
+    }
+}
+
+// splits off the next whitespace separated field
+fn next_field(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[.. end], &text[end ..])
+}
+
+// splits an operation into its opcode and operand tokens, keeping quoted strings whole
+fn split_operation(op: &str) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    let mut chars = op.chars().peekable();
+
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        match chars.peek() {
+            None => break,
+            Some(&'"') => {
+                chars.next();
+                let s: String = chars.by_ref().take_while(|c| *c != '"').collect();
+                tokens.push((s, true));
+            },
+            Some(_) => {
+                let mut s = String::new();
+                while chars.peek().map_or(false, |c| !c.is_whitespace()) {
+                    s.push(chars.next().unwrap());
+                }
+                tokens.push((s, false));
+            }
+        }
+    }
+
+    tokens
+}
+
+// Parses one EPD record: the four position fields of a FEN string, then
+// operations like `bm Nf3 Nc3; id "test 1";`. Move counters after the four
+// fields are accepted too, since many files include them.
+pub fn parse_epd(line: &str) -> Result<EpdEntry, EpdError> {
+    let mut fen_fields = Vec::new();
+    let mut rest = line.trim();
+
+    for _ in 0 .. 4 {
+        let (field, remainder) = next_field(rest);
+        if field.is_empty() {
+            return Err(EpdError::InvalidFen(line.trim().to_string()));
+        }
+        fen_fields.push(field);
+        rest = remainder;
+    }
+
+    for _ in 0 .. 2 {
+        let (field, remainder) = next_field(rest);
+        if field.is_empty() || !field.chars().all(|c| c.is_ascii_digit()) {
+            break;
+        }
+        fen_fields.push(field);
+        rest = remainder;
+    }
+
+    let fen = fen_fields.join(" ");
+    let mut game = match Game::from_fen_str(&fen) {
+        Some(g) => g,
+        None => return Err(EpdError::InvalidFen(fen))
+    };
+
+    // operations end at semicolons outside of quoted strings
+    let mut op_strs = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+
+    for c in rest.chars() {
+        match c {
+            '"' => { in_string = !in_string; current.push(c); },
+            ';' if !in_string => op_strs.push(current.split_off(0)),
+            _ => current.push(c)
+        }
+    }
+
+    if in_string {
+        return Err(EpdError::InvalidOperation(current.trim().to_string()));
+    }
+    op_strs.push(current);
+
+    let mut entry = EpdEntry::new(game);
+
+    for op_str in op_strs.iter().filter(|s| !s.trim().is_empty()) {
+        let mut tokens = split_operation(op_str).into_iter();
+
+        let opcode = match tokens.next() {
+            Some((ref opcode, false)) if opcode.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) => opcode.clone(),
+            _ => return Err(EpdError::InvalidOperation(op_str.trim().to_string()))
+        };
+
+        let mut operands = Vec::new();
+        let mut pv_game = game;
+
+        for (token, quoted) in tokens {
+            let operand = if quoted {
+                EpdOperand::Str(token)
+            } else if is_move_opcode(&opcode) || opcode == "pv" {
+                match move_from_san(&pv_game, &token) {
+                    Some(m) => {
+                        if opcode == "pv" {
+                            pv_game.make_move(m);
+                        }
+                        EpdOperand::Move(m)
+                    },
+                    None => return Err(EpdError::IllegalMove { opcode: opcode, san: token })
+                }
+            } else if is_integer_opcode(&opcode) {
+                let (lowest, highest) = integer_range(&opcode);
+                match token.parse::<i64>() {
+                    Ok(x) if x >= lowest && x <= highest => EpdOperand::Integer(x),
+                    _ => return Err(EpdError::InvalidNumber { opcode: opcode, value: token })
+                }
+            } else {
+                match token.parse::<i64>() {
+                    Ok(x) => EpdOperand::Integer(x),
+                    Err(_) => EpdOperand::Symbol(token)
+                }
+            };
+
+            operands.push(operand);
+        }
+
+        if operands.is_empty() && (is_move_opcode(&opcode) || is_integer_opcode(&opcode)) {
+            return Err(EpdError::InvalidOperation(op_str.trim().to_string()));
+        }
+
+        entry.operations.push(EpdOperation { opcode: opcode, operands: operands });
+    }
+
+    // the counters can also be given as operations
+    if let Some(x) = entry.first_integer("hmvc") {
+        game.halfmove_clock = x as u8;
+    }
+    if let Some(x) = entry.first_integer("fmvn") {
+        game.fullmoves = x as u16;
+    }
+    entry.game = game;
+
+    Ok(entry)
+}
+
+// Parses a file with one EPD record per line, skipping blank lines and
+// lines starting with `#`.
+pub fn parse_epd_file(text: &str) -> Result<Vec<EpdEntry>, String> {
+    let mut entries = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match parse_epd(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => return Err(format!("line {}: {}", line_idx + 1, e))
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use epd::*;
+    use movegen::*;
+
+    fn uci_moves(moves: Vec<Move>) -> Vec<String> {
+        moves.iter().map(|m| m.to_uci_str()).collect()
+    }
+
+    #[test]
+    fn parsing() {
+        let entry = parse_epd("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\"; c0 \"mate; in 3\"; ce 32000; acd 12;").unwrap();
+
+        assert_eq!(entry.game.to_fen(), "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1");
+        assert_eq!(uci_moves(entry.best_moves()), vec!["g3g6"]);
+        assert!(entry.avoid_moves().is_empty());
+        assert_eq!(entry.id(), Some("WAC.001"));
+        assert_eq!(entry.comment(0), Some("mate; in 3"));
+        assert_eq!(entry.comment(1), None);
+        assert_eq!(entry.centipawn_eval(), Some(32000));
+        assert_eq!(entry.operation("acd").unwrap().operands, vec![EpdOperand::Integer(12)]);
+
+        let entry = parse_epd("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - am f3 g4; pv e4 e5 Nf3 Nc6; hmvc 4; fmvn 12; xx yy 3").unwrap();
+        assert_eq!(uci_moves(entry.avoid_moves()), vec!["f2f3", "g2g4"]);
+        assert_eq!(uci_moves(entry.pv()), vec!["e2e4", "e7e5", "g1f3", "b8c6"]);
+        assert_eq!(entry.game.halfmove_clock, 4);
+        assert_eq!(entry.game.fullmoves, 12);
+        assert_eq!(entry.operation("xx").unwrap().operands,
+                   vec![EpdOperand::Symbol("yy".to_string()), EpdOperand::Integer(3)]);
+
+        // perft suites, with move counters and leading semicolons
+        let entry = parse_epd("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 66").unwrap();
+        assert_eq!(entry.perft_counts(), vec![(1, 15), (2, 66)]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse_epd("").is_err());
+        assert!(parse_epd("4k3/8/8/8/8/8 w K - bm Kd1;").is_err());
+        assert_eq!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - bm Ke3;"),
+                   Err(EpdError::IllegalMove { opcode: "bm".to_string(), san: "Ke3".to_string() }));
+        assert_eq!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - D1 many;"),
+                   Err(EpdError::InvalidNumber { opcode: "D1".to_string(), value: "many".to_string() }));
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - bm;").is_err());
+
+        // counters and node counts that don't fit are rejected rather than wrapped
+        assert_eq!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - hmvc -1;"),
+                   Err(EpdError::InvalidNumber { opcode: "hmvc".to_string(), value: "-1".to_string() }));
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - hmvc 256;").is_err());
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - fmvn 70000;").is_err());
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - D1 -5;").is_err());
+        assert_eq!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - hmvc 255; ce -40;").unwrap().game.halfmove_clock, 255);
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - id \"unterminated;").is_err());
+
+        assert_eq!(parse_epd_file("# suite\n\n4k3/8/8/8/8/8/8/4K2R w K - bm O-O;\n").unwrap().len(), 1);
+        assert_eq!(parse_epd_file("4k3/8/8/8/8/8/8/4K2R w K - bm O-O;\n\nbad\n"),
+                   Err("line 3: invalid position: bad".to_string()));
+    }
+
+    #[test]
+    fn writing() {
+        let epd = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - bm Bb5 Bc4; pv Bb5 a6 Ba4; id \"Ruy\"; ce -15; D1 30;";
+        let entry = parse_epd(epd).unwrap();
+        assert_eq!(entry.to_epd_string(), epd);
+
+        let mut entry = EpdEntry::new(Game::starting_position());
+        let e4 = next_moves_standalone(&entry.game).iter().find(|m| m.to_uci_str() == "e2e4").map(|m| *m).unwrap();
+        entry.set_operation("bm", vec![EpdOperand::Move(e4)]);
+        entry.set_operation("c0", vec![EpdOperand::Str("king's pawn".to_string())]);
+        assert_eq!(entry.to_epd_string(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; c0 \"king's pawn\";");
+        assert_eq!(parse_epd(&entry.to_epd_string()), Ok(entry));
+    }
+}
@@ -5,12 +5,17 @@ use game::*;
 use tables::*;
 use movegen::*;
 
+use std::cmp::{max, min};
+
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct Score(i16);
 
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub struct Phase(u16);
 
+// scores this close to max/min are mates, offset by the ply they occur at
+const MAX_MATE_PLY: i16 = 256;
+
 impl Score {
     pub fn new(s: i16) -> Score {
         debug_assert!(s >= Score::min().0 && s <=Score::max().0);
@@ -37,6 +42,12 @@ impl Score {
         Score::new(self.0 * -1)
     }
 
+    // shifts the score by some centipawns, staying within min() and max()
+    pub fn offset(self, delta: i16) -> Score {
+        let shifted = self.0 as i32 + delta as i32;
+        Score::new(min(max(shifted, Score::min().0 as i32), Score::max().0 as i32) as i16)
+    }
+
     pub fn store_u16(&self) -> u16 {
         (self.0 as i32 - i16::min_value() as i32) as u16
     }
@@ -49,6 +60,60 @@ impl Score {
         self.0
     }
 
+    pub fn is_mate(self) -> bool {
+        self.0 >= Score::max().0 - MAX_MATE_PLY || self.0 <= Score::min().0 + MAX_MATE_PLY
+    }
+
+    // full moves until mate, negative if the side to move is getting mated
+    pub fn mate_in(self) -> Option<i16> {
+        if !self.is_mate() {
+            return None;
+        }
+
+        if self.0 > 0 {
+            Some((Score::max().0 - self.0 + 1) / 2)
+        } else {
+            Some(-(self.0 - Score::min().0 + 1) / 2)
+        }
+    }
+
+    pub fn to_uci_str(self) -> String {
+        match self.mate_in() {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", self.0)
+        }
+    }
+
+    // xboard reports a mate in N moves as 100000 + N
+    pub fn to_xboard_score(self) -> i32 {
+        match self.mate_in() {
+            Some(moves) if moves > 0 => 100000 + moves as i32,
+            Some(moves) => -100000 + moves as i32,
+            None => self.0 as i32
+        }
+    }
+
+    // Mate scores are relative to the root, but the table is shared between
+    // plies, so they are stored relative to the node instead.
+    pub fn to_table(self, ply: usize) -> Score {
+        if !self.is_mate() {
+            self
+        } else if self.0 > 0 {
+            Score::new(min(self.0 + ply as i16, Score::max().0))
+        } else {
+            Score::new(max(self.0 - ply as i16, Score::min().0))
+        }
+    }
+
+    pub fn from_table(self, ply: usize) -> Score {
+        if !self.is_mate() {
+            self
+        } else if self.0 > 0 {
+            Score::new(self.0 - ply as i16)
+        } else {
+            Score::new(self.0 + ply as i16)
+        }
+    }
 }
 
 impl Phase {
@@ -252,6 +317,21 @@ const KING_TABLE: [(i16,i16); 64] =
 mod test {
     use eval::*;
 
+    #[test]
+    fn mate_scores() {
+        assert_eq!(Score::max_at_depth(1).mate_in(), Some(1));
+        assert_eq!(Score::max_at_depth(5).mate_in(), Some(3));
+        assert_eq!(Score::min_at_depth(2).mate_in(), Some(-1));
+        assert_eq!(Score::new(250).mate_in(), None);
+        assert_eq!(Score::max_at_depth(3).to_uci_str(), "mate 2");
+        assert_eq!(Score::new(-37).to_uci_str(), "cp -37");
+
+        for ply in 0 .. 10 {
+            let s = Score::max_at_depth(12);
+            assert_eq!(s.to_table(ply).from_table(ply), s);
+        }
+    }
+
     #[test]
     fn flip() {
         for _ in 0 .. 100000 {
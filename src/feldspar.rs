@@ -1,107 +1,410 @@
 use eval::*;
 use core::*;
 use game::*;
+use logging::*;
 use movegen::*;
 use moves::*;
+use san::*;
 use search::*;
 use tree::*;
 use uci::*;
 use zobrist::*;
 
-use std::time::Instant;
-use std::cmp::max;
+use std::time::{Duration, Instant};
+use std::cmp::{max, min};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
 
 use std::str::SplitWhitespace;
 
+pub const DEFAULT_HASH_MB: usize = 256;
+const MAX_HASH_MB: usize = 65536;
+const DEFAULT_MOVE_OVERHEAD_MS: u32 = 30;
+const MAX_MULTI_PV: usize = 64;
+
+// which GUI protocol the search output is written for
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Protocol {
+    UCI,
+    XBoard
+}
+
+// everything set by the protocol driver that the search thread needs to know about
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct EngineSettings {
+    hash_mb: usize,
+    move_overhead: u32,
+    multi_pv: usize,
+    protocol: Protocol,
+    // whether to print thinking output, xboard can turn it off with `nopost`
+    post: bool
+}
+
+impl EngineSettings {
+    fn new() -> EngineSettings {
+        EngineSettings {
+            hash_mb: DEFAULT_HASH_MB,
+            move_overhead: DEFAULT_MOVE_OVERHEAD_MS,
+            multi_pv: 1,
+            protocol: Protocol::UCI,
+            post: true
+        }
+    }
+}
+
+// messages sent from the protocol thread to the search thread
+enum SearchCommand {
+    Go(SearchLimits),
+    ReplaceGame(Game, Vec<Hash>),
+    Configure(EngineSettings),
+    Ping(Sender<()>),
+    ReportMovesTo(MoveReporter),
+    Reset,
+    Quit
+}
+
+// called on the search thread with the chosen move at the end of every search
+pub type MoveReporter = Box<Fn(Move) + Send>;
+
 pub struct Feldspar {
-    context: SearchContext
+    game: Game,
+    settings: EngineSettings,
+    commands: Sender<SearchCommand>,
+    abort: Arc<AtomicBool>,
+    pondering: Arc<AtomicBool>,
+    searching: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>
+}
+
+// Owns the search state and lives on its own thread, so that the protocol
+// thread stays free to read `stop`/`quit`/`isready` while a search is running.
+struct SearchWorker {
+    context: SearchContext,
+    settings: EngineSettings,
+    searching: Arc<AtomicBool>,
+    move_reporter: Option<MoveReporter>
 }
 
 impl Feldspar {
     pub fn new() -> Feldspar {
-        let mut tmp_tree = SearchTree::new(Game::starting_position());
-        let mut tmp_qtree = SearchTree::new(Game::starting_position());
-        tmp_qtree.in_quiescence = true;
-        let mut tmp_table = TranspositionTable::new(100000000);
-
-        let mut new_context = SearchContext {
-            tree: tmp_tree,
-            qtree: tmp_qtree,
-            table: tmp_table,
-            timer: SearchTimer::new(3000),
-            ran_out_of_time: false
-        };
+        let (sender, receiver) = channel();
+        let abort = Arc::new(AtomicBool::new(false));
+        let pondering = Arc::new(AtomicBool::new(false));
+        let searching = Arc::new(AtomicBool::new(false));
+
+        let worker_abort = abort.clone();
+        let worker_pondering = pondering.clone();
+        let worker_searching = searching.clone();
+
+        // allocate the table up front, so that it is ready before we answer `isready`
+        let settings = EngineSettings::new();
+        let table = TranspositionTable::with_size_mb(settings.hash_mb);
+
+        // SearchTree holds non-Send move buffers, so the rest of the search state
+        // has to be created on the thread that uses it.
+        let worker = thread::Builder::new()
+            .name("search".to_string())
+            .spawn(move || {
+                let mut context = SearchContext::new(Game::starting_position(), table, worker_abort);
+                context.report_currmove = true;
+                context.pondering = worker_pondering;
+                let mut worker = SearchWorker {
+                    context: context,
+                    settings: settings,
+                    searching: worker_searching,
+                    move_reporter: None
+                };
+                worker.run(receiver);
+            })
+            .expect("failed to spawn search thread");
 
         Feldspar {
-            context: new_context
+            game: Game::starting_position(),
+            settings: settings,
+            commands: sender,
+            abort: abort,
+            pondering: pondering,
+            searching: searching,
+            worker: Some(worker)
+        }
+    }
+
+    fn wait_for_search(&self) {
+        while self.searching.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
         }
     }
+
+    fn send(&self, cmd: SearchCommand) {
+        if self.commands.send(cmd).is_err() {
+            error!("search thread is no longer running!");
+        }
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.settings.protocol = protocol;
+        self.settings.post = protocol == Protocol::UCI;
+        self.send(SearchCommand::Configure(self.settings));
+    }
+
+    pub fn set_post(&mut self, post: bool) {
+        self.settings.post = post;
+        self.send(SearchCommand::Configure(self.settings));
+    }
+
+    // the reporter is called before the search is marked as finished,
+    // so the move is always delivered by the time `stop` returns
+    pub fn report_moves_to(&mut self, reporter: MoveReporter) {
+        self.send(SearchCommand::ReportMovesTo(reporter));
+    }
 }
 
 impl UCIEngine for Feldspar {
     fn name(&self) -> &'static str { "feldspar" }
     fn author(&self) -> &'static str { "Zac Meadows" }
 
-    //TODO: print promotion type!
-    fn find_best_move(&mut self, wtime: u32, btime: u32, winc: u32, binc: u32) -> () {
-
-        let mut my_time = 0;
-        let mut opp_time = 0;
-        let mut my_inc = 0;
-        let mut opp_inc = 0;
+    fn reset(&mut self) {
+        self.stop();
+        self.send(SearchCommand::Reset);
+    }
 
-        if self.context.tree.focus().to_move == Color::White {
-            my_time = wtime;
-            opp_time = btime;
-            my_inc = winc;
-            opp_inc = binc;
-        } else {
-            my_time = btime;
-            opp_time = wtime;
-            my_inc = binc;
-            opp_inc = winc;
+    fn wait_until_ready(&mut self) {
+        // during a search `isready` has to be answered straight away
+        if self.searching.load(Ordering::SeqCst) {
+            return;
         }
 
-        if my_time > opp_time {
-            self.context.timer = SearchTimer::new( max(my_time - opp_time, my_time/50) );
-        } else {
-            if my_time > 10000 {
-                self.context.timer = SearchTimer::new( max(my_time/40, 1500) );
-            } else {
-                self.context.timer = SearchTimer::new( max(my_time/40, 500) );
+        let (sender, receiver) = channel();
+        self.send(SearchCommand::Ping(sender));
+        let _ = receiver.recv();
+    }
+
+    fn options(&self) -> Vec<UCIOption> {
+        vec![
+            UCIOption::spin("Hash", DEFAULT_HASH_MB as i64, 1, MAX_HASH_MB as i64),
+            UCIOption::button("Clear Hash"),
+            UCIOption::spin("Move Overhead", DEFAULT_MOVE_OVERHEAD_MS as i64, 0, 5000),
+            // Only advertised because many GUIs send it unconditionally. The search
+            // is single-threaded, so 1 is the only value accepted, and it's ignored.
+            UCIOption::spin("Threads", 1, 1, 1),
+            UCIOption::spin("MultiPV", 1, 1, MAX_MULTI_PV as i64),
+            // tells the GUI that we understand `go ponder`
+            UCIOption::check("Ponder", false),
+            UCIOption::string("Log File", ""),
+            UCIOption::combo("Log Level", DEFAULT_LOG_LEVEL, LOG_LEVELS.to_vec())
+        ]
+    }
+
+    fn set_option(&mut self, name: &'static str, value: UCIOptionValue) {
+        match (name, value) {
+            ("Hash", UCIOptionValue::Spin(mb)) => self.settings.hash_mb = mb as usize,
+            ("Clear Hash", _) => {
+                self.send(SearchCommand::Reset);
+                return;
+            },
+            ("Move Overhead", UCIOptionValue::Spin(ms)) => self.settings.move_overhead = ms as u32,
+            // fixed at 1, see options()
+            ("Threads", _) => return,
+            ("Ponder", _) => return,
+            ("MultiPV", UCIOptionValue::Spin(n)) => self.settings.multi_pv = n as usize,
+            ("Log File", UCIOptionValue::String(path)) => {
+                if let Err(e) = set_log_file(&path) {
+                    send!("info string couldn't open log file {}: {}", path, e);
+                }
+                return;
+            },
+            ("Log Level", UCIOptionValue::Combo(level)) => {
+                if let Some(level) = parse_log_level(&level) {
+                    set_log_level(level);
+                }
+                return;
+            },
+            (name, value) => {
+                warn!("unhandled option: {} = {:?}", name, value);
+                return;
             }
         }
 
-        self.context.ran_out_of_time = false;
+        self.send(SearchCommand::Configure(self.settings));
+    }
 
-        let mut depth_reached = 0;
-        let mut best_move = Move::null();
-        let mut best_score = Score::min();
+    fn current_game(&self) -> Game {
+        self.game
+    }
 
-        for i in 1 .. 999 {
-            negamax( &mut self.context, i, Score::min(), Score::max() );
-            if !self.context.ran_out_of_time {
-                depth_reached = i;
-                let pv = self.context.table.get_pv(*self.context.tree.focus(), depth_reached as usize);
-                if pv.len() > 0 {
-                    best_move = pv[0].best_move();
-                    best_score = pv[0].score();
+    fn find_best_move(&mut self, limits: SearchLimits) -> () {
+        // a GUI shouldn't send `go` during a search, but if it does the old one is dropped
+        self.stop();
+
+        self.abort.store(false, Ordering::SeqCst);
+        self.pondering.store(limits.ponder, Ordering::SeqCst);
+        self.searching.store(true, Ordering::SeqCst);
+        self.send(SearchCommand::Go(limits));
+    }
+
+    fn ponder_hit(&mut self) {
+        debug!(target: SEARCH_TARGET, "ponderhit, switching to our own clock");
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    fn stop(&mut self) {
+        self.pondering.store(false, Ordering::SeqCst);
+
+        if self.searching.load(Ordering::SeqCst) {
+            debug!(target: SEARCH_TARGET, "stopping search");
+            self.abort.store(true, Ordering::SeqCst);
+            self.wait_for_search();
+        }
+    }
 
-                    let mut pv_str = String::new();
+    fn quit(&mut self) {
+        self.abort.store(true, Ordering::SeqCst);
+        self.send(SearchCommand::Quit);
 
-                    for entry in pv.iter() {
-                        if pv_str.len() > 0 {
-                            pv_str.push_str(" ");
-                        }
-                        pv_str.push_str(&entry.best_move().to_uci_str());
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("search thread panicked!");
+            }
+        }
+    }
+
+    fn replace_game(&mut self, new_game: Game, history: Vec<Hash>) {
+        self.game = new_game;
+        self.send(SearchCommand::ReplaceGame(new_game, history));
+    }
+}
+
+impl SearchWorker {
+    fn run(&mut self, commands: Receiver<SearchCommand>) {
+        for cmd in commands.iter() {
+            match cmd {
+                SearchCommand::Go(limits) => {
+                    self.find_best_move(limits);
+                    self.searching.store(false, Ordering::SeqCst);
+                },
+                SearchCommand::ReplaceGame(new_game, history) => {
+                    self.context.tree.reset_root(new_game, history);
+                },
+                SearchCommand::Configure(new_settings) => {
+                    if new_settings.hash_mb != self.settings.hash_mb {
+                        debug!(target: SEARCH_TARGET, "resizing hash table to {} MB", new_settings.hash_mb);
+                        // free the old table before allocating the new one
+                        self.context.table = TranspositionTable::new(1);
+                        self.context.table = TranspositionTable::with_size_mb(new_settings.hash_mb);
                     }
+                    self.settings = new_settings;
+                    self.context.report_currmove = new_settings.protocol == Protocol::UCI;
+                },
+                SearchCommand::ReportMovesTo(reporter) => {
+                    self.move_reporter = Some(reporter);
+                },
+                SearchCommand::Ping(reply) => {
+                    let _ = reply.send(());
+                },
+                SearchCommand::Reset => self.context.new_game(),
+                SearchCommand::Quit => return
+            }
+        }
+    }
+
+    // milliseconds to spend on this move, less the time lost talking to the GUI
+    fn allocate_time(&self, limits: &SearchLimits) -> u32 {
+        match self.raw_time_budget(limits) {
+            t if t == u32::max_value() => t,
+            t => max(t.saturating_sub(self.settings.move_overhead), 1)
+        }
+    }
+
+    fn raw_time_budget(&self, limits: &SearchLimits) -> u32 {
+        if limits.infinite {
+            return u32::max_value();
+        }
 
-                    println!("info depth {} score cp {} pv {}", depth_reached, best_score.unwrap(), pv_str);
-                    eprintln!("best_move from negamax: {}{}", best_move.from().to_algebraic(), best_move.to().to_algebraic());
+        if let Some(movetime) = limits.movetime {
+            return movetime;
+        }
+
+        let (my_time, opp_time, my_inc) = match self.context.tree.focus().to_move {
+            Color::White => (limits.wtime, limits.btime, limits.winc),
+            Color::Black => (limits.btime, limits.wtime, limits.binc)
+        };
+
+        // no clock given, so only the depth/nodes/mate limits apply
+        let my_time = match my_time {
+            Some(t) => t,
+            None => return u32::max_value()
+        };
+
+        let opp_time = opp_time.unwrap_or(my_time);
+
+        let budget = if let Some(moves_to_go) = limits.movestogo {
+            my_time / (moves_to_go + 1)
+        } else if my_time > opp_time {
+            max(my_time - opp_time, my_time/50)
+        } else if my_time > 10000 {
+            max(my_time/40, 1500)
+        } else {
+            max(my_time/40, 500)
+        };
+
+        // the increment is added back after the move, so most of it can be spent now,
+        // but never so much that the clock runs out
+        min(budget.saturating_add(my_inc / 4 * 3), my_time - my_time / 10)
+    }
+
+    fn find_best_move(&mut self, limits: SearchLimits) -> () {
+        let time_budget = self.allocate_time(&limits);
+
+        debug!(target: SEARCH_TARGET, "searching {} with {:?}", self.context.tree.focus().to_fen(), limits);
+        debug!(target: SEARCH_TARGET, "time budget: {} ms", time_budget);
+
+        // while pondering the clock isn't running, the budget is applied on ponderhit
+        if limits.ponder {
+            self.context.timer = SearchTimer::new(u32::max_value());
+            self.context.ponder_time = Some(time_budget);
+        } else {
+            self.context.timer = SearchTimer::new(time_budget);
+            self.context.ponder_time = None;
+        }
+
+        self.context.limits = limits;
+
+        let settings = self.settings;
+
+        let result = iterative_deepening(&mut self.context, |context, depth, score, best_move| {
+            trace!(target: SEARCH_TARGET, "depth {} done after {} nodes", depth, context.nodes);
+            print_line(context, &settings, depth, 1, score);
+
+            // with MultiPV each further line is searched with the earlier ones excluded
+            context.excluded_root_moves.push(best_move);
+
+            for pv_idx in 1 .. settings.multi_pv {
+                let (line_score, line_move) = negamax(context, depth, Score::min(), Score::max());
+
+                if context.aborted() || line_move.is_null() {
+                    break;
                 }
-            } else {
-                break;
+
+                print_line(context, &settings, depth, pv_idx + 1, line_score);
+                context.excluded_root_moves.push(line_move);
             }
+
+            true
+        });
+
+        let best_move = result.best_move;
+
+        debug!(target: SEARCH_TARGET, "search ended at depth {} after {} nodes and {} ms{}",
+               result.depth, self.context.nodes, self.context.timer.elapsed_ms(),
+               if self.context.aborted() { " (aborted)" } else { "" });
+
+        // a finished ponder or infinite search still has to wait for ponderhit or
+        // stop, the only things that abort it
+        while (self.context.limits.infinite || self.context.pondering()) && !self.context.aborted() {
+            thread::sleep(Duration::from_millis(1));
         }
 
         // match self.context.tree.focus().to_move {
@@ -109,17 +412,147 @@ impl UCIEngine for Feldspar {
         //     Color::Black => eprintln!("score: {:?}", (best_score.flipped().unwrap() as f32)/100.0)
         // }
 
-        println!( "bestmove {}{}"
-                , best_move.from().to_algebraic()
-                , best_move.to().to_algebraic()
-                );
+        // the reply we expect is the second move of the principal variation
+        let pv = self.context.table.get_pv(*self.context.tree.focus(), 2);
+        let ponder_move = if pv.len() == 2 && pv[0].best_move() == best_move {
+            Some(pv[1].best_move())
+        } else {
+            None
+        };
 
-        self.context.ran_out_of_time = false;
+        // the xboard driver announces the move itself, since it may have to be discarded
+        if self.settings.protocol == Protocol::UCI {
+            match ponder_move {
+                Some(m) => send!("bestmove {} ponder {}", best_move.to_uci_str(), m.to_uci_str()),
+                None => send!("bestmove {}", best_move.to_uci_str())
+            }
+        }
 
-        //TODO: ponder while opponent thinks
+        if let Some(ref report) = self.move_reporter {
+            report(best_move);
+        }
     }
+}
 
-    fn replace_game(&mut self, new_game: Game, history: Vec<Hash>) {
-        self.context.tree.reset_root(new_game, history);
+fn print_line(context: &SearchContext, settings: &EngineSettings, depth: u8, multi_pv_idx: usize, score: Score) {
+    if !settings.post {
+        return;
+    }
+
+    let root = *context.tree.focus();
+    let pv: Vec<Move> = context.table.get_pv(root, depth as usize)
+        .iter()
+        .map(|entry| entry.best_move())
+        .collect();
+
+    let time = context.timer.elapsed_ms();
+    let nps = context.nodes * 1000 / max(time, 1);
+
+    if settings.protocol == Protocol::XBoard {
+        // ply, score, time in centiseconds, nodes, pv (in SAN, which xboard displays as is)
+        send!("{} {} {} {} {}", depth, score.to_xboard_score(), time / 10, context.nodes,
+              moves_to_san(&root, &pv).join(" "));
+        return;
+    }
+
+    let pv_str: Vec<String> = pv.iter().map(|m| m.to_uci_str()).collect();
+    let pv_str = pv_str.join(" ");
+
+    let mut info = format!("info depth {} seldepth {}", depth, context.seldepth);
+
+    if settings.multi_pv > 1 {
+        info.push_str(&format!(" multipv {}", multi_pv_idx));
+    }
+
+    send!("{} score {} nodes {} nps {} hashfull {} time {} pv {}"
+            , info
+            , score.to_uci_str()
+            , context.nodes
+            , nps
+            , context.table.hashfull()
+            , time
+            , pv_str
+            );
+}
+
+#[cfg(test)]
+mod test {
+    use feldspar::*;
+
+    #[test]
+    fn time_budget() {
+        let mut worker = SearchWorker {
+            context: SearchContext::new(Game::starting_position(), TranspositionTable::with_size_mb(1), Arc::new(AtomicBool::new(false))),
+            settings: EngineSettings::new(),
+            searching: Arc::new(AtomicBool::new(false)),
+            move_reporter: None
+        };
+
+        let mut limits = SearchLimits::new();
+        limits.wtime = Some(60000);
+        limits.btime = Some(60000);
+        assert_eq!(worker.raw_time_budget(&limits), 1500);
+
+        // only the increment of the side to move counts
+        limits.winc = 2000;
+        limits.binc = 8000;
+        assert_eq!(worker.raw_time_budget(&limits), 3000);
+
+        // a large increment can't be spent before it arrives
+        limits.wtime = Some(1000);
+        limits.btime = Some(1000);
+        limits.winc = 10000;
+        assert_eq!(worker.raw_time_budget(&limits), 900);
+
+        worker.context.tree.reset_root(Game::from_fen_str("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap(), Vec::new());
+        limits.wtime = Some(60000);
+        limits.btime = Some(60000);
+        assert_eq!(worker.raw_time_budget(&limits), 7500);
+    }
+
+    #[test]
+    fn infinite_search_waits_for_stop() {
+        init_zobrist_hashing();
+
+        let mut engine = Feldspar::new();
+        let (sender, receiver) = channel();
+        engine.report_moves_to(Box::new(move |m| { let _ = sender.send(m); }));
+
+        // the whole tree is searched to the maximum depth in no time
+        engine.replace_game(Game::from_fen_str("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap(), Vec::new());
+
+        let mut limits = SearchLimits::new();
+        limits.infinite = true;
+        engine.find_best_move(limits);
+
+        thread::sleep(Duration::from_millis(500));
+        assert!(receiver.try_recv().is_err());
+
+        engine.stop();
+        assert!(receiver.try_recv().is_ok());
+        engine.quit();
+    }
+
+    #[test]
+    fn ponder_search_waits_for_ponderhit() {
+        init_zobrist_hashing();
+
+        let mut engine = Feldspar::new();
+        let (sender, receiver) = channel();
+        engine.report_moves_to(Box::new(move |m| { let _ = sender.send(m); }));
+
+        // the node limit is reached long before ponderhit
+        let mut limits = SearchLimits::new();
+        limits.ponder = true;
+        limits.nodes = Some(1000);
+        engine.find_best_move(limits);
+
+        thread::sleep(Duration::from_millis(500));
+        assert!(receiver.try_recv().is_err());
+
+        // from then on the limit applies, and it has already been spent
+        engine.ponder_hit();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        engine.quit();
     }
 }
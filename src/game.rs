@@ -16,7 +16,37 @@ pub enum GameResult {
     Draw
 }
 
-#[derive(PartialEq, Clone, Copy)]
+// how a game was decided, including the draws the search tree doesn't track
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GameEnd {
+    Checkmate(Color),
+    Stalemate,
+    FiftyMoveRule,
+    Repetition
+}
+
+impl GameEnd {
+    // the result as written in PGN
+    pub fn score_str(&self) -> &'static str {
+        match *self {
+            GameEnd::Checkmate(Color::White) => "1-0",
+            GameEnd::Checkmate(Color::Black) => "0-1",
+            _ => "1/2-1/2"
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            GameEnd::Checkmate(Color::White) => "White mates",
+            GameEnd::Checkmate(Color::Black) => "Black mates",
+            GameEnd::Stalemate => "Stalemate",
+            GameEnd::FiftyMoveRule => "Draw by fifty move rule",
+            GameEnd::Repetition => "Draw by repetition"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Game {
     pub board: Board,
     pub to_move: Color,
@@ -142,7 +172,14 @@ impl Game {
 
     pub fn from_fen_str<'a>(fen: &'a str) -> Option<Game> {
         let mut fen_split = fen.split_whitespace();
-        Game::from_fen(&mut fen_split)
+        let game = Game::from_fen(&mut fen_split);
+
+        // with the counters optional, a bad counter would otherwise be left over
+        if fen_split.next().is_some() {
+            return None;
+        }
+
+        game
     }
 
     pub fn from_fen<'a>(args: &mut SplitWhitespace<'a>) -> Option<Game> {
@@ -152,56 +189,74 @@ impl Game {
         use Color::*;
 
         { // build up the game board
-            let mut current_square: Square = Square::new(63);
-
-            let decrement_square = |sq: &mut Square, n: u32| {
-                if sq.unwrap() >= n {
-                    *sq = Square::new(sq.unwrap() - n);
-                } else {
-                    *sq = Square::new(0);
-                }
+            let ranks: Vec<&str> = match args.next() {
+                Some(placement) => placement.split('/').collect(),
+                None => return None
             };
 
-            let mut add_piece = |piece_color: Color, piece_type: PieceType, sq: &mut Square| {
-                game.board.set_piece_bit(piece_color, piece_type, *sq);
-                decrement_square(sq, 1);
-            };
+            if ranks.len() != 8 {
+                return None;
+            }
+
+            for (rank_idx, rank) in ranks.iter().enumerate() {
+                // squares are counted down from h8 (63) to a1 (0)
+                let mut squares_filled: u32 = 0;
+
+                for ch in rank.chars() {
+                    let piece = match ch {
+                        'p' => Some((Black , Pawn  )),
+                        'n' => Some((Black , Knight)),
+                        'b' => Some((Black , Bishop)),
+                        'r' => Some((Black , Rook  )),
+                        'q' => Some((Black , Queen )),
+                        'k' => Some((Black , King  )),
+                        'P' => Some((White , Pawn  )),
+                        'N' => Some((White , Knight)),
+                        'B' => Some((White , Bishop)),
+                        'R' => Some((White , Rook  )),
+                        'Q' => Some((White , Queen )),
+                        'K' => Some((White , King  )),
+                        '1' ..= '8' => None,
+                        _ => return None
+                    };
 
-            for ch in args.next().expect("Missing FEN string").chars() {
-                match ch {
-                    'p' => add_piece(Black , Pawn   , &mut current_square) ,
-                    'n' => add_piece(Black , Knight , &mut current_square) ,
-                    'b' => add_piece(Black , Bishop , &mut current_square) ,
-                    'r' => add_piece(Black , Rook   , &mut current_square) ,
-                    'q' => add_piece(Black , Queen  , &mut current_square) ,
-                    'k' => add_piece(Black , King   , &mut current_square) ,
-                    'P' => add_piece(White , Pawn   , &mut current_square) ,
-                    'N' => add_piece(White , Knight , &mut current_square) ,
-                    'B' => add_piece(White , Bishop , &mut current_square) ,
-                    'R' => add_piece(White , Rook   , &mut current_square) ,
-                    'Q' => add_piece(White , Queen  , &mut current_square) ,
-                    'K' => add_piece(White , King   , &mut current_square) ,
-                    '1' => decrement_square(&mut current_square, 1),
-                    '2' => decrement_square(&mut current_square, 2),
-                    '3' => decrement_square(&mut current_square, 3),
-                    '4' => decrement_square(&mut current_square, 4),
-                    '5' => decrement_square(&mut current_square, 5),
-                    '6' => decrement_square(&mut current_square, 6),
-                    '7' => decrement_square(&mut current_square, 7),
-                    '8' => decrement_square(&mut current_square, 8),
-                    '/' => {},
-                    _ => return None
+                    match piece {
+                        Some((piece_color, piece_type)) => {
+                            if squares_filled >= 8 {
+                                return None;
+                            }
+                            let sq = Square::new(63 - 8 * rank_idx as u32 - squares_filled);
+                            game.board.set_piece_bit(piece_color, piece_type, sq);
+                            squares_filled += 1;
+                        },
+                        None => squares_filled += ch.to_digit(10).unwrap()
+                    }
                 }
+
+                if squares_filled != 8 {
+                    return None;
+                }
+            }
+        }
+
+        for color in [White, Black].iter() {
+            if game.board.get_pieces(*color, King).population() != 1 {
+                return None;
             }
         }
 
-        match args.next().expect("Missing color-to-move in FEN string") {
-            "w" => game.to_move = White,
-            "b" => game.to_move = Black,
+        match args.next() {
+            Some("w") => game.to_move = White,
+            Some("b") => game.to_move = Black,
             _ => return None
         }
 
-        for ch in args.next().expect("Missing castling rights in FEN string").chars() {
+        let castling_str = match args.next() {
+            Some(x) => x,
+            None => return None
+        };
+
+        for ch in castling_str.chars() {
             match ch {
                 'K' => game.castling_rights |= CastlingRights::WHITE_KINGSIDE,
                 'Q' => game.castling_rights |= CastlingRights::WHITE_QUEENSIDE,
@@ -212,21 +267,31 @@ impl Game {
             }
         }
 
-        match Square::from_algebraic(args.next().expect("Missing en-passante square in FEN string")) {
-            None => game.ep_square = None,
-            Some(sq) => game.ep_square = Some(sq)
+        match args.next() {
+            Some("-") => game.ep_square = None,
+            Some(sq_str) => match Square::from_algebraic(sq_str) {
+                None => return None,
+                Some(sq) => game.ep_square = Some(sq)
+            },
+            None => return None
         }
 
-        match args.next().expect("Missing fifty move count in FEN string").parse::<u8>() {
-            Err(_) => return None,
-            Ok(x) => game.halfmove_clock = x
-        }
+        // The move counters are often left out, e.g. in EPD, so they are
+        // only consumed when present, leaving a following `moves` in place.
+        game.halfmove_clock = 0;
+        game.fullmoves = 1;
 
-        match args.next().expect("Missing move count in FEN string").parse::<u16>() {
-            Err(_) => return None,
-            Ok(x) => game.fullmoves = x
+        if let Some(Ok(x)) = args.clone().next().map(|x| x.parse::<u8>()) {
+            args.next();
+            game.halfmove_clock = x;
+
+            if let Some(Ok(x)) = args.clone().next().map(|x| x.parse::<u16>()) {
+                args.next();
+                game.fullmoves = x;
+            }
         }
 
+        
         let king_square     = game.board.get_king_square(game.to_move);
         game.king_attackers = game.board.attackers(king_square, !game.to_move);
 
@@ -235,6 +300,26 @@ impl Game {
         return Some(game);
     }
 
+    // `history` holds the hash of every position of the game, this one included
+    pub fn game_end(&self, history: &[Hash]) -> Option<GameEnd> {
+        match self.outcome {
+            Some(GameResult::Win(color)) => return Some(GameEnd::Checkmate(color)),
+            Some(GameResult::Draw) => return Some(GameEnd::Stalemate),
+            None => {}
+        }
+
+        if self.halfmove_clock >= 100 {
+            return Some(GameEnd::FiftyMoveRule);
+        }
+
+        let repetitions = history.iter().filter(|h| **h == self.hash).count();
+        if repetitions >= 3 {
+            return Some(GameEnd::Repetition);
+        }
+
+        None
+    }
+
     pub fn make_null_move(&mut self) {
         debug_assert!(!self.in_check());
 
@@ -249,11 +334,15 @@ impl Game {
         self.to_move = !self.to_move;
         self.hash.update_black_to_move();
 
+        // passing gives up the chance to capture en passant
+        if let Some(ep_square) = self.ep_square.take() {
+            self.hash.modify_ep_square(ep_square);
+        }
+
         let opp_king_square = self.board.get_king_square(opponent_color);
         self.king_attackers = self.board.attackers(opp_king_square, !self.to_move);
 
         let can_move = can_move(self);
-        self.ep_square = None;
 
         // no moves available, game is over
         if !can_move {
@@ -592,6 +681,47 @@ mod test {
             let g = Game::from_fen_str(fen).unwrap();
             assert!(&g.to_fen() == fen);
         }
+
+        // the move counters are optional
+        let g = Game::from_fen_str("r1bqkbnr/pp1npp1p/2pp2p1/8/2PPP3/2N1B3/PP3PPP/R2QKBNR b KQkq -").unwrap();
+        assert_eq!(g.to_fen(), "r1bqkbnr/pp1npp1p/2pp2p1/8/2PPP3/2N1B3/PP3PPP/R2QKBNR b KQkq - 0 1");
+
+        let mut args = "8/8/8/8/8/8/8/K6k w - - 3 moves a1a2".split_whitespace();
+        let g = Game::from_fen(&mut args).unwrap();
+        assert_eq!(g.halfmove_clock, 3);
+        assert_eq!(g.fullmoves, 1);
+        assert_eq!(args.next(), Some("moves"));
+
+        assert!(Game::from_fen_str("8/8/8/8/8/8/8/K6k w - - zero 1").is_none());
+    }
+
+    #[test]
+    fn game_end() {
+        init_zobrist_hashing();
+
+        let mut g = Game::from_fen_str("7k/8/6K1/8/8/8/5Q2/8 w - - 0 1").unwrap();
+        g.make_move(move_from_algebraic(&g, "f2f7".to_string()).unwrap());
+        assert_eq!(g.game_end(&[]), Some(GameEnd::Stalemate));
+
+        let g = Game::from_fen_str("4k3/8/8/8/8/8/8/4K2R w K - 100 80").unwrap();
+        assert_eq!(g.game_end(&[]), Some(GameEnd::FiftyMoveRule));
+
+        let g = Game::from_fen_str("4k3/8/8/8/8/8/8/4K2R w K - 4 10").unwrap();
+        assert_eq!(g.game_end(&[g.hash, g.hash]), None);
+        assert_eq!(g.game_end(&[g.hash, g.hash, g.hash]), Some(GameEnd::Repetition));
+        assert_eq!(GameEnd::Repetition.score_str(), "1/2-1/2");
+        assert_eq!(GameEnd::Checkmate(Color::Black).score_str(), "0-1");
+    }
+
+    #[test]
+    fn null_move() {
+        init_zobrist_hashing();
+
+        // the en passant square is gone once the side to move passes
+        let mut g = Game::from_fen_str("rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2").unwrap();
+        g.make_null_move();
+        assert_eq!(g.ep_square, None);
+        assert_eq!(g.hash, Hash::new(&g));
     }
 
     #[test]
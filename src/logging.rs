@@ -0,0 +1,101 @@
+use log;
+use log::{Log, Metadata, Record, LevelFilter};
+use chrono::Local;
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::sync::{Mutex, Once};
+
+// Protocol transcripts are logged under these targets at `info`, search events
+// under `search` at `debug` and `trace`. Nothing is ever written to stdout,
+// which belongs to the GUI.
+pub const INPUT_TARGET: &'static str = "input";
+pub const OUTPUT_TARGET: &'static str = "output";
+pub const SEARCH_TARGET: &'static str = "search";
+
+pub const LOG_LEVELS: [&'static str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+pub const DEFAULT_LOG_LEVEL: &'static str = "info";
+
+// prints a line to the GUI and records it in the output transcript
+macro_rules! send {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{}", line);
+        info!(target: ::logging::OUTPUT_TARGET, "{}", line);
+    }}
+}
+
+struct FileLogger;
+
+static LOGGER: FileLogger = FileLogger;
+
+// Mutex::new can't be called in a static initializer, so the file is set up
+// on first use
+static mut LOG_FILE: *const Mutex<Option<File>> = 0 as *const Mutex<Option<File>>;
+static LOG_FILE_INIT: Once = Once::new();
+
+fn log_file() -> &'static Mutex<Option<File>> {
+    unsafe {
+        LOG_FILE_INIT.call_once(|| {
+            LOG_FILE = Box::into_raw(Box::new(Mutex::new(None)));
+        });
+        &*LOG_FILE
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(mut guard) = log_file().lock() {
+            if let Some(ref mut file) = *guard {
+                let _ = writeln!(file, "{} {:<5} [{}] {}",
+                                 Local::now().format("%H:%M:%S%.3f"),
+                                 record.level(),
+                                 record.target(),
+                                 record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut guard) = log_file().lock() {
+            if let Some(ref mut file) = *guard {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+// installs the file logger, nothing is recorded until a log file is set
+pub fn init_logging(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+pub fn parse_log_level(level: &str) -> Option<LevelFilter> {
+    level.parse().ok()
+}
+
+pub fn set_log_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+// an empty path stops logging
+pub fn set_log_file(path: &str) -> io::Result<()> {
+    let new_file = if path.is_empty() {
+        None
+    } else {
+        Some(OpenOptions::new().create(true).append(true).open(path)?)
+    };
+
+    if let Ok(mut guard) = log_file().lock() {
+        *guard = new_file;
+    }
+
+    Ok(())
+}
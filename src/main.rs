@@ -7,6 +7,7 @@
 #![feature(plugin, custom_attribute)]
 
 #[macro_use] extern crate bitflags;
+#[macro_use] extern crate log;
 #[macro_use] extern crate prettytable;
 extern crate num_cpus;
 extern crate rand;
@@ -16,13 +17,18 @@ extern crate chrono;
 use clap::App;
 
 use std::fs::File;
+use std::io::BufReader;
 use std::thread;
 use std::process;
 
+#[macro_use] mod logging; use logging::*;
 mod search; use search::*;
+mod bench; use bench::*;
+mod analyze; use analyze::*;
 mod bitboard; use bitboard::*;
 mod board; use board::*;
 mod core; use core::*;
+mod epd; use epd::*;
 mod eval; use eval::*;
 mod feldspar; use feldspar::*;
 mod game; use game::*;
@@ -30,57 +36,233 @@ mod movegen; use movegen::*;
 mod moves; use moves::*;
 mod move_list; use move_list::*;
 mod perft; use perft::*;
+mod pgn; use pgn::*;
 mod pins; use pins::*;
 mod play; use play::*;
 mod print; use print::*;
+mod san; use san::*;
 mod tables; use tables::*;
+mod testsuite; use testsuite::*;
 mod uci; use uci::*;
+mod xboard; use xboard::*;
 mod zobrist; use zobrist::*;
 mod tree; use tree::*;
 
 const FELDSPAR_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+// exits with an error if the argument is given but can't be parsed
+fn parse_arg<T: std::str::FromStr>(value: Option<&str>, name: &str) -> Option<T> {
+    value.map(|v| match v.parse() {
+        Ok(x) => x,
+        Err(_) => {
+            eprintln!("Invalid value for {}: {}", name, v);
+            process::exit(1);
+        }
+    })
+}
+
 fn main() {
     let yaml = load_yaml!("../cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
 
+    let log_level = parse_log_level(matches.value_of("log-level").unwrap_or(DEFAULT_LOG_LEVEL));
+    match log_level {
+        Some(level) => init_logging(level),
+        None => {
+            eprintln!("Invalid log level passed: {}", matches.value_of("log-level").unwrap());
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = matches.value_of("log-file") {
+        if let Err(e) = set_log_file(path) {
+            eprintln!("Couldn't open log file {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+
     init_zobrist_hashing();
     use Color::*;
     use PieceType::*;
 
-    if matches.is_present("ponder") {
-        let ponder_FEN = matches.value_of("ponder").unwrap();
-        match Game::from_fen_str(ponder_FEN) {
-            None => {
-                eprintln!("Invalid FEN string passed: {}", ponder_FEN);
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        let depth = match bench_matches.value_of("depth").map(|d| d.parse::<u8>()) {
+            Some(Ok(d)) if d >= 1 && d < MAX_SEARCH_DEPTH => d,
+            Some(_) => {
+                eprintln!("bench depth must be between 1 and {}", MAX_SEARCH_DEPTH - 1);
                 process::exit(1);
+            },
+            None => DEFAULT_BENCH_DEPTH
+        };
+
+        bench(depth);
+    } else if let Some(analyze_matches) = matches.subcommand_matches("analyze") {
+        let fen = analyze_matches.value_of("fen").unwrap_or("startpos");
+        let game = match fen {
+            "startpos" => Game::starting_position(),
+            fen => match Game::from_fen_str(fen) {
+                Some(g) => g,
+                None => {
+                    eprintln!("Invalid FEN string passed: {}", fen);
+                    process::exit(1);
+                }
+            }
+        };
+
+        let mut limits = SearchLimits::new();
+        limits.depth = parse_arg(analyze_matches.value_of("depth"), "depth");
+        limits.movetime = parse_arg(analyze_matches.value_of("movetime"), "movetime");
+        limits.nodes = parse_arg(analyze_matches.value_of("nodes"), "nodes");
+
+        let hash_mb = parse_arg(analyze_matches.value_of("hashsize"), "hashsize").unwrap_or(DEFAULT_HASH_MB);
+
+        let format = if analyze_matches.is_present("json") {
+            OutputFormat::Json
+        } else if analyze_matches.is_present("epd") {
+            OutputFormat::Epd
+        } else {
+            game.board.print();
+            println!("{}", game.to_fen());
+            OutputFormat::Human
+        };
+
+        analyze(game, limits, hash_mb, format);
+    } else if let Some(play_matches) = matches.subcommand_matches("play") {
+        let fen = play_matches.value_of("fen").unwrap_or("startpos");
+        let game = match fen {
+            "startpos" => Game::starting_position(),
+            fen => match Game::from_fen_str(fen) {
+                Some(g) => g,
+                None => {
+                    eprintln!("Invalid FEN string passed: {}", fen);
+                    process::exit(1);
+                }
             }
+        };
 
-            Some(game) => {
-                game.board.print();
-                println!("{}", game.to_fen());
-                let mut tmp_tree = SearchTree::new(game);
-                let mut tmp_qtree = SearchTree::new(game);
-                tmp_qtree.in_quiescence = true;
-                let mut tmp_table = TranspositionTable::new(20000000);
-
-                let mut context = SearchContext {
-                    tree: tmp_tree,
-                    qtree: tmp_qtree,
-                    table: tmp_table,
-                    timer: SearchTimer::new(u32::max_value()),
-                    ran_out_of_time: false
-                };
-
-                for i in 1 .. {
-                    let (s,m) = negamax(&mut context, i, Score::min(), Score::max());
-                    m.print();
+        let user_color = match play_matches.value_of("side") {
+            Some(side) => match parse_side(side) {
+                Some(c) => c,
+                None => {
+                    eprintln!("--side must be white or black");
+                    process::exit(1);
                 }
+            },
+            None => match prompt_for_side() {
+                Some(c) => c,
+                None => return
             }
+        };
+
+        let movetime_ms = parse_arg(play_matches.value_of("movetime"), "movetime").unwrap_or(DEFAULT_PLAY_MOVETIME_MS);
+
+        let mut session = match play_matches.value_of("pgn") {
+            Some(path) => {
+                let first_game = File::open(path)
+                    .map_err(|e| PgnError::Io(e.to_string()))
+                    .and_then(|f| PgnReader::new(BufReader::new(f)).next().unwrap_or(Err(PgnError::Io("no games found".to_string()))));
+
+                match first_game {
+                    Ok(pgn) => {
+                        println!("Continuing {} - {}", pgn.tag("White").unwrap_or("?"), pgn.tag("Black").unwrap_or("?"));
+                        PlaySession::from_pgn(&pgn, user_color, movetime_ms)
+                    },
+                    Err(e) => {
+                        eprintln!("{}: {}", path, e);
+                        process::exit(1);
+                    }
+                }
+            },
+            None => PlaySession::new(game, user_color, movetime_ms)
+        };
+
+        session.run();
+    } else if let Some(suite_matches) = matches.subcommand_matches("testsuite") {
+        let path = suite_matches.value_of("file").unwrap();
+        let movetime_ms = parse_arg(suite_matches.value_of("movetime"), "movetime").unwrap_or(DEFAULT_TEST_MOVETIME_MS);
+        let hash_mb = parse_arg(suite_matches.value_of("hashsize"), "hashsize").unwrap_or(DEFAULT_HASH_MB);
+
+        if let Err(e) = test_suite(path, movetime_ms, hash_mb) {
+            eprintln!("{}", e);
+            process::exit(1);
         }
     } else if matches.is_present("perft") {
+        let perft_fen = matches.value_of("perft").unwrap();
+        let game = match perft_fen {
+            "startpos" => Game::starting_position(),
+            fen => match Game::from_fen_str(fen) {
+                Some(g) => g,
+                None => {
+                    eprintln!("Invalid FEN string passed: {}", fen);
+                    process::exit(1);
+                }
+            }
+        };
+
+        let depth = match matches.value_of("depth").map(|d| d.parse::<usize>()) {
+            Some(Ok(d)) if d >= 1 && d < MAX_PERFT_DEPTH => d,
+            Some(_) => {
+                eprintln!("--depth must be between 1 and {}", MAX_PERFT_DEPTH - 1);
+                process::exit(1);
+            },
+            None => {
+                eprintln!("--perft requires --depth");
+                process::exit(1);
+            }
+        };
+
+        if let Some(engine_path) = matches.value_of("compare") {
+            let mut engine = match UCIPerftEngine::spawn(engine_path) {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+            match perft_debug(game, depth, &mut |g: &Game, d| engine.divide(g, d)) {
+                Ok(None) => println!("All move counts match {} to depth {}", engine_path, depth),
+                Ok(Some((g, differences))) => {
+                    g.board.print();
+                    println!("{}", g.to_fen());
+                    for d in differences.iter() {
+                        println!("{}", d);
+                    }
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        } else if matches.is_present("divide") {
+            print_perft_divide(game, depth);
+        } else if matches.is_present("fast") {
+            let table_mb = match matches.value_of("hashsize").map(|h| h.parse::<usize>()) {
+                Some(Ok(mb)) => mb,
+                Some(Err(_)) => {
+                    eprintln!("--hashsize must be a number of megabytes");
+                    process::exit(1);
+                },
+                None => DEFAULT_PERFT_TABLE_MB
+            };
+
+            print_perft_nodes(game, depth, &mut PerftTable::with_size_mb(table_mb));
+        } else {
+            let threads = match matches.value_of("threads").map(|t| t.parse::<usize>()) {
+                Some(Ok(t)) if t >= 1 => t,
+                Some(_) => {
+                    eprintln!("--threads must be a positive number");
+                    process::exit(1);
+                },
+                None => num_cpus::get()
+            };
+
+            perft(game, depth, threads);
+        }
     } else if matches.is_present("uci") {
         Feldspar::new().run();
+    } else if matches.is_present("xboard") {
+        XBoard::new(Feldspar::new()).run();
     }
 
 
@@ -102,9 +284,6 @@ fn main() {
     //     x.join();
     // }
 
-    //play_against_ai();
-
-
     // for m in next_moves_standalone(&g).iter() {
     //     let mut game_copy = g.clone();
     //     game_copy.make_move(*m);
@@ -32,6 +32,13 @@ impl MoveList {
     pub fn at(&self, idx: usize) -> Move { return self.moves[idx]; }
 
     pub fn sort(&mut self, best_move_candidate: Option<Move>) {
+        self.sort_with_quiet_scores(best_move_candidate, |_| 0);
+    }
+
+    // quiet moves go after the captures, highest score first
+    pub fn sort_with_quiet_scores<F>(&mut self, best_move_candidate: Option<Move>, quiet_score: F)
+        where F: Fn(Move) -> i32
+    {
         self.moves[..self.count].sort_by(|m1, m2| {
             if (best_move_candidate.is_some()) {
                 if (*m1 == best_move_candidate.unwrap()) {
@@ -62,7 +69,7 @@ impl MoveList {
                     return Ordering::Equal;
                 }
             } else {
-                return Ordering::Equal;
+                return quiet_score(*m2).cmp(&quiet_score(*m1));
             }
         });
     }
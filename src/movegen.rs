@@ -6,6 +6,7 @@ use game::*;
 use move_list::*;
 use bitboard::*;
 
+use std::cmp::{max, min};
 use std::rc::Rc;
 use std::cell::RefCell;
 use rand::Rng;
@@ -26,14 +27,11 @@ pub fn next_moves_standalone_chunked(game: &Game, chunks: usize) -> Vec<Vec<Move
     let buf = alloc_move_buffer();
     generate_moves(&game, buf.clone(), false);
 
-    let mut move_chunks = Vec::new();
+    let chunk_count = min(max(chunks, 1), max(buf.borrow().len(), 1));
+    let mut move_chunks = vec![Vec::new(); chunk_count];
 
     for (i, m) in buf.borrow().iter().enumerate() {
-        if move_chunks.len() <= i {
-            move_chunks.push(Vec::new());
-        }
-
-        move_chunks[i % chunks].push(*m);
+        move_chunks[i % chunk_count].push(*m);
     }
 
     return move_chunks;
@@ -533,7 +531,7 @@ pub fn generate_moves(game: &Game, buffer: MoveBuffer, captures_only: bool) {
 
 //NOTE: highly inefficient, but this will rarely be used.
 pub fn move_from_algebraic(game: &Game, move_str: String) -> Option<Move> {
-    if move_str.len() !=4 && move_str.len() != 5 {
+    if !move_str.is_ascii() || (move_str.len() != 4 && move_str.len() != 5) {
         return None;
     }
 
@@ -127,9 +127,36 @@ impl Move {
         self.0 == 0x0
     }
 
+    pub fn promoted_piece(&self) -> Option<PieceType> {
+        if !self.is_promotion() {
+            return None;
+        }
+
+        // the capture bit doesn't matter here
+        match self.flag() & 0b1011 {
+            KNIGHT_PROMO_FLAG => Some(PieceType::Knight),
+            BISHOP_PROMO_FLAG => Some(PieceType::Bishop),
+            ROOK_PROMO_FLAG => Some(PieceType::Rook),
+            _ => Some(PieceType::Queen)
+        }
+    }
+
     pub fn to_uci_str(&self) -> String {
-        //TODO: add promotion type
-        format!("{}{}", self.from().to_algebraic(), self.to().to_algebraic())
+        if self.is_null() {
+            return "0000".to_string();
+        }
+
+        let mut uci_str = format!("{}{}", self.from().to_algebraic(), self.to().to_algebraic());
+
+        match self.promoted_piece() {
+            Some(PieceType::Knight) => uci_str.push('n'),
+            Some(PieceType::Bishop) => uci_str.push('b'),
+            Some(PieceType::Rook) => uci_str.push('r'),
+            Some(_) => uci_str.push('q'),
+            None => {}
+        }
+
+        return uci_str;
     }
 }
 
@@ -191,4 +218,18 @@ mod test {
             assert!(cm.captured_piece().unwrap() == captured_ptype);
         }
     }
+
+    #[test]
+    fn uci_str() {
+        use PieceType::*;
+
+        let quiet = Move::new_quiet(Square::from_algebraic("e2").unwrap(), Square::from_algebraic("e4").unwrap(), DOUBLE_PAWN_PUSH_FLAG, Pawn);
+        let promo = Move::new_quiet(Square::from_algebraic("a7").unwrap(), Square::from_algebraic("a8").unwrap(), KNIGHT_PROMO_FLAG, Pawn);
+        let promo_capture = Move::new_capture(Square::from_algebraic("b2").unwrap(), Square::from_algebraic("a1").unwrap(), QUEEN_PROMO_CAPTURE_FLAG, Pawn, Rook);
+
+        assert_eq!(quiet.to_uci_str(), "e2e4");
+        assert_eq!(promo.to_uci_str(), "a7a8n");
+        assert_eq!(promo_capture.to_uci_str(), "b2a1q");
+        assert_eq!(Move::null().to_uci_str(), "0000");
+    }
 }
@@ -3,26 +3,32 @@
 use bitboard::*;
 use board::*;
 use core::*;
+use epd::*;
 use game::*;
 use movegen::*;
 use moves::*;
 use tables::*;
 use tree::*;
+use zobrist::*;
 
+use std::cmp::max;
 use std::collections::HashMap;
 use std::cell::RefCell;
+use std::fmt;
 use std::thread;
 use std::ops::Add;
 use std::os;
-use std::process::Command;
+use std::mem::size_of;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
 
+use num_cpus;
 use prettytable::Table;
 use prettytable::cell::Cell;
 use prettytable::row::Row;
 
-//TODO: read from ENV variable
-const QPERFT_PATH: &'static str = "/Users/zac/Code/qperft/qperft";
-const MAX_PERFT_DEPTH: usize = 20;
+pub const MAX_PERFT_DEPTH: usize = 20;
+pub const DEFAULT_PERFT_TABLE_MB: usize = 256;
 
 struct PerftContext {
     tree: SearchTree,
@@ -95,76 +101,277 @@ impl PerftContext {
         let next_moves = self.tree.next_moves(None);
 
         for m in next_moves.borrow().iter() {
-            let game_copy = *self.tree.focus();
+            self.visit(*m, max_depth);
+        }
+    }
 
-            self.tree.make_move(*m);
+    // only searches the given subset of the moves at the root
+    fn go_from_root(&mut self, root_moves: &[Move], max_depth: usize) {
+        debug_assert!(self.tree.search_depth() == 0);
 
-            self.result.node_count[self.tree.search_depth()] += 1;
+        for m in root_moves.iter() {
+            self.visit(*m, max_depth);
+        }
+    }
 
-            if m.flag() == EP_CAPTURE_FLAG {
-                self.result.ep_captures[self.tree.search_depth()] += 1;
-            }
+    fn visit(&mut self, m: Move, max_depth: usize) {
+        let game_copy = *self.tree.focus();
 
-            if m.is_capture() {
-                self.result.captures[self.tree.search_depth()] += 1;
-            }
+        self.tree.make_move(m);
 
-            if m.flag() == KING_CASTLE_FLAG || m.flag() == QUEEN_CASTLE_FLAG {
-                self.result.castles[self.tree.search_depth()] += 1;
-            }
+        self.result.node_count[self.tree.search_depth()] += 1;
 
-            if m.is_promotion() {
-                self.result.promotions[self.tree.search_depth()] += 1;
-            }
+        if m.flag() == EP_CAPTURE_FLAG {
+            self.result.ep_captures[self.tree.search_depth()] += 1;
+        }
 
-            if self.tree.focus().in_check() {
-                self.result.checks[self.tree.search_depth()] += 1;
-            }
+        if m.is_capture() {
+            self.result.captures[self.tree.search_depth()] += 1;
+        }
 
-            match self.tree.focus().outcome {
-                Some(GameResult::Win(_)) => self.result.check_mates[self.tree.search_depth()] += 1,
-                _ => {}
-            }
+        if m.flag() == KING_CASTLE_FLAG || m.flag() == QUEEN_CASTLE_FLAG {
+            self.result.castles[self.tree.search_depth()] += 1;
+        }
+
+        if m.is_promotion() {
+            self.result.promotions[self.tree.search_depth()] += 1;
+        }
+
+        if self.tree.focus().in_check() {
+            self.result.checks[self.tree.search_depth()] += 1;
+        }
+
+        match self.tree.focus().outcome {
+            Some(GameResult::Win(_)) => self.result.check_mates[self.tree.search_depth()] += 1,
+            _ => {}
+        }
+
+        self.go(max_depth);
+        self.tree.unmake_move(game_copy);
+    }
+}
+
+// the root moves are split between the threads, and their results summed
+pub fn perft_parallel(game: Game, depth: usize, threads: usize) -> PerftResult {
+    let mut handles = Vec::new();
+
+    for move_subset in next_moves_standalone_chunked(&game, threads) {
+        handles.push(thread::spawn(move || {
+            let mut pc = PerftContext::new(game);
+            pc.go_from_root(&move_subset, depth);
+            // the root is counted once, in the merged result
+            pc.result.node_count[0] = 0;
+            pc.result
+        }));
+    }
+
+    let mut final_result = PerftResult::new();
+
+    for handle in handles {
+        match handle.join() {
+            Ok(result) => final_result = final_result + result,
+            Err(_) => panic!("Failed to join threads for PERFT test.")
+        }
+    }
+
+    return final_result;
+}
+
+// A subtree's leaf count, keyed by position and the depth below it.
+// Entries with depth zero are empty, nothing that shallow is stored.
+#[derive(Debug, PartialEq, Clone)]
+struct PerftEntry {
+    hash: Hash,
+    depth: usize,
+    count: usize
+}
+
+impl PerftEntry {
+    fn empty() -> PerftEntry {
+        PerftEntry { hash: Hash::empty(), depth: 0, count: 0 }
+    }
+}
+
+pub struct PerftTable {
+    entries: Vec<PerftEntry>
+}
+
+impl PerftTable {
+    pub fn new(count: usize) -> PerftTable {
+        PerftTable {
+            entries: vec![PerftEntry::empty(); max(count, 1)]
+        }
+    }
+
+    pub fn with_size_mb(megabytes: usize) -> PerftTable {
+        PerftTable::new(megabytes * 1024 * 1024 / size_of::<PerftEntry>())
+    }
+
+    // the same position at different depths shouldn't compete for one slot
+    fn index(&self, hash: Hash, depth: usize) -> usize {
+        let key = hash.unwrap() ^ (depth as u64).wrapping_mul(0x9e3779b97f4a7c15);
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&self, hash: Hash, depth: usize) -> Option<usize> {
+        let entry = &self.entries[self.index(hash, depth)];
+
+        if entry.depth == depth && entry.hash == hash {
+            Some(entry.count)
+        } else {
+            None
+        }
+    }
+
+    pub fn update(&mut self, hash: Hash, depth: usize, count: usize) {
+        let idx = self.index(hash, depth);
+        self.entries[idx] = PerftEntry { hash: hash, depth: depth, count: count };
+    }
+}
 
-            self.go(max_depth);
-            self.tree.unmake_move(game_copy);
+// Counts leaf nodes only: the last ply is counted straight from the move
+// list rather than played out, and subtree counts are cached.
+struct NodeCountContext<'a> {
+    move_buffers: Vec<MoveBuffer>,
+    table: &'a mut PerftTable
+}
+
+impl<'a> NodeCountContext<'a> {
+    fn new(max_depth: usize, table: &'a mut PerftTable) -> NodeCountContext<'a> {
+        NodeCountContext {
+            move_buffers: (0 .. max_depth + 1).map(|_| alloc_move_buffer()).collect(),
+            table: table
+        }
+    }
+
+    fn count(&mut self, game: &Game, depth: usize) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+
+        let buffer = self.move_buffers[depth].clone();
+        generate_moves(game, buffer.clone(), false);
+
+        if depth == 1 {
+            return buffer.borrow().len();
+        }
+
+        if let Some(count) = self.table.probe(game.hash, depth) {
+            return count;
+        }
+
+        let mut total = 0;
+
+        for m in buffer.borrow().iter() {
+            let mut game_copy = *game;
+            game_copy.make_move(*m);
+            total += self.count(&game_copy, depth - 1);
         }
+
+        self.table.update(game.hash, depth, total);
+
+        return total;
     }
 }
 
+// equal to perft(game, depth, _).node_count[depth], but much faster
+pub fn perft_nodes(game: Game, depth: usize, table: &mut PerftTable) -> usize {
+    init_zobrist_hashing();
 
-pub fn perft(game: Game, depth: usize) -> PerftResult {
-    // let num_cpus = num_cpus::get() - 2;
-    // let mut threads = Vec::new();
+    let mut nc = NodeCountContext::new(depth, table);
+    nc.count(&game, depth)
+}
 
+pub fn print_perft_nodes(game: Game, depth: usize, table: &mut PerftTable) {
     let start_time = Counter::new();
+    let nodes = perft_nodes(game, depth, table);
+
+    println!("Nodes at depth {}: {}", depth, nodes);
+    println!("MNodes/Sec: {:.2}", 1e-6 * nodes as f64 / (start_time.elapsed_ms() / 1000.0));
+}
+
+// FEN strings followed by the expected node counts, e.g. `<fen> ;D1 20 ;D2 400`
+pub const PERFT_SUITE: &'static str = include_str!("../suites/perftsuite.epd");
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PerftSuiteEntry {
+    pub fen: String,
+    // (depth, nodes) pairs
+    pub expected: Vec<(usize, usize)>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PerftSuiteFailure {
+    pub fen: String,
+    pub depth: usize,
+    pub expected: usize,
+    pub found: usize
+}
+
+impl fmt::Display for PerftSuiteFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at depth {}: expected {} nodes, found {}", self.fen, self.depth, self.expected, self.found)
+    }
+}
 
-    // for move_subset in next_moves_standalone_chunked(&game, num_cpus) {
+pub fn parse_perft_suite(suite: &str) -> Result<Vec<PerftSuiteEntry>, String> {
+    let mut entries = Vec::new();
 
-    //     threads.push(thread::spawn(move || {
+    for (line_idx, line) in suite.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let bad_line = || format!("line {}: {}", line_idx + 1, line);
 
-    //         for m in move_subset {
-    //             let game_clone = game.clone();
-    //             let mut pc = PerftContext::new(game_clone);
-    //             return pc.go(depth);
-    //         }
+        let epd = parse_epd(line).map_err(|e| format!("line {}: {}", line_idx + 1, e))?;
+        let expected = epd.perft_counts();
+
+        // anything but node counts for depths we can search is a mistake
+        if expected.len() != epd.operations.len() {
+            return Err(bad_line());
+        }
 
-    //     }));
-    // }
+        if expected.iter().any(|&(depth, _)| depth == 0 || depth >= MAX_PERFT_DEPTH) {
+            return Err(bad_line());
+        }
+
+        entries.push(PerftSuiteEntry { fen: epd.game.to_fen(), expected: expected });
+    }
 
-    // let mut final_result = PerftResult::new();
+    return Ok(entries);
+}
+
+// checks every expected count up to max_depth, returning the ones that are wrong
+pub fn run_perft_suite(entries: &[PerftSuiteEntry], max_depth: usize) -> Vec<PerftSuiteFailure> {
+    let mut table = PerftTable::with_size_mb(64);
+    let mut failures = Vec::new();
+
+    for entry in entries.iter() {
+        let game = Game::from_fen_str(&entry.fen).unwrap();
+
+        for &(depth, expected) in entry.expected.iter().filter(|&&(d, _)| d <= max_depth) {
+            let found = perft_nodes(game, depth, &mut table);
+
+            if found != expected {
+                failures.push(PerftSuiteFailure {
+                    fen: entry.fen.clone(),
+                    depth: depth,
+                    expected: expected,
+                    found: found
+                });
+            }
+        }
+    }
 
-    // for thread in threads {
-    //     match thread.join() {
-    //         Ok(result) => final_result = final_result + result,
-    //         Err(_) => println!("Failed to join threads for PERFT test.")
-    //     }
-    // }
+    return failures;
+}
 
-    let mut pc = PerftContext::new(game.clone());
-    pc.go(depth);
+pub fn perft(game: Game, depth: usize, threads: usize) -> PerftResult {
+    let start_time = Counter::new();
 
-    let final_result = &pc.result;
+    let final_result = &perft_parallel(game, depth, threads);
 
     let mut table = Table::new();
     table.add_row(row![
@@ -212,137 +419,341 @@ pub fn perft(game: Game, depth: usize) -> PerftResult {
     game.board.print();
     table.print_tty(false);
 
-    // println!("Threads used: {}", num_cpus);
+    println!("Threads used: {}", max(threads, 1));
     println!("Total Nodes Processed: {}", total_nodes);
     println!("MNodes/Sec: {:.2}", 1e-6 * total_nodes as f64 / (start_time.elapsed_ms() / 1000.0));
 
     return final_result.clone();
 }
 
-// pub fn perft_divide(game: Game, depth: usize) -> HashMap<String, u32> {
-//
-//     let mut move_gen = MoveGen::new();
-//     let move_buffer = move_gen.move_list(&game);
-//     let mut results = HashMap::new();
-//
-//     for m in &move_buffer {
-//         let mut game_copy = game.clone();
-//         game_copy.make_move(*m);
-//         let mut nc = NodeCountContext::new(game_copy);
-//         nc.go(1,depth-1);
-//         let mut f = m.from().to_algebraic();
-//         f.push_str(&m.to().to_algebraic());
-//
-//         results.insert(f, nc.node_count as u32);
-//     }
-//
-//     return results;
-// }
-
-// pub fn qperft_divide(game: Game, depth: usize) -> HashMap<String, u32> {
-//     let qperft_command = [
-//         &depth.to_string(),
-//         &["-", &(depth-1).to_string()].join(""),
-//         &game.to_fen()
-//     ];
-//
-//     let qperft_output = Command::new(QPERFT_PATH).args(&qperft_command).output().expect("");
-//
-//     let qperft_output_str: String = String::from_utf8_lossy(&qperft_output.stdout).to_string();
-//
-//     let delimit1: String = format!("perft( {}", depth-1);
-//     let delimit2: String = format!("perft( {}", depth);
-//
-//     let mut save = false;
-//     let mut relevant_lines = Vec::new();
-//
-//     for line in qperft_output_str.split("\n") {
-//         if (line.contains(&delimit2)) {
-//             save = false;
-//         }
-//
-//         if save && line.chars().nth(0).unwrap() == '2' && line.chars().nth(1).unwrap() == '.' {
-//             relevant_lines.push(line);
-//         }
-//
-//         if (line.contains(&delimit1)) {
-//             save = true;
-//         }
-//     }
-//
-//     let mut qperft_results_map = HashMap::new();
-//
-//     for line in &relevant_lines {
-//         let split_line: Vec<&str> = line.split_whitespace().collect();
-//         qperft_results_map.insert(split_line[1].to_string(), split_line[4].parse::<u32>().unwrap());
-//     }
-//
-//     return qperft_results_map;
-// }
-//
-// pub fn qperft_debug(game: Game) {
-//
-//     for depth in 3 .. 8 {
-//         println!("depth: {}", depth);
-//         let qperft_results = qperft_divide(game.clone(), depth);
-//         let feldspar_results = perft_divide(game.clone(), depth);
-//         println!("{} {}", qperft_results.len(), feldspar_results.len());
-//
-//         if (qperft_results.len() != feldspar_results.len()) {
-//             game.board.print();
-//             println!("{}", game.to_fen());
-//
-//             for (m,s) in &qperft_results {
-//                 match feldspar_results.get(m) {
-//                     Some(fs) => {},
-//                     None => {
-//                         println!("feldspar missing move: {}", m);
-//                     }
-//                 }
-//             }
-//
-//             for (m,s) in feldspar_results {
-//                 match qperft_results.get(&m) {
-//                     Some(fs) => {},
-//                     None => {
-//                         println!("feldspar generated illegal move: {}", m);
-//                     }
-//                 }
-//             }
-//
-//             return;
-//         }
-//
-//         for (m,s) in qperft_results {
-//             match feldspar_results.get(&m) {
-//                 Some(fs) =>
-//                     if *fs != s {
-//                         println!("{} {} {}", m, s, fs);
-//
-//                         match move_from_algebraic(game.clone(), m) {
-//                             Some(mv) => {
-//                                 mv.print();
-//                                 let mut game_copy = game.clone();
-//                                 game_copy.make_move(mv, &mut MoveGen::new());
-//                                 println!("{}", game_copy.to_fen());
-//                                 game_copy.board.print();
-//                                 qperft_debug(game_copy);
-//                                 return;
-//                             },
-//
-//                             None => { println!("unexpected weirdness"); }
-//                         }
-//                     },
-//                 None => {}
-//             }
-//         }
-//     }
-// }
+// number of leaf nodes below each legal move at the root
+pub fn perft_divide(game: Game, depth: usize) -> Vec<(Move, usize)> {
+    debug_assert!(depth >= 1);
+
+    let mut results = Vec::new();
+
+    for m in next_moves_standalone(&game).iter() {
+        let mut game_copy = game.clone();
+        game_copy.make_move(*m);
+
+        let mut pc = PerftContext::new(game_copy);
+        pc.go(depth - 1);
+
+        results.push((*m, pc.result.node_count[depth - 1]));
+    }
+
+    return results;
+}
+
+pub fn print_perft_divide(game: Game, depth: usize) {
+    let mut results = perft_divide(game, depth);
+    results.sort_by_key(|&(m, _)| m.to_uci_str());
+
+    let mut total_nodes: usize = 0;
+
+    for &(m, count) in results.iter() {
+        println!("{}: {}", m.to_uci_str(), count);
+        total_nodes += count;
+    }
+
+    println!("");
+    println!("Moves: {}", results.len());
+    println!("Total Nodes: {}", total_nodes);
+}
+
+pub type DivideMap = HashMap<String, usize>;
+
+pub fn divide_map(game: Game, depth: usize) -> DivideMap {
+    perft_divide(game, depth).into_iter().map(|(m, count)| (m.to_uci_str(), count)).collect()
+}
+
+// how feldspar's divide differs from a reference divide
+#[derive(Debug, PartialEq, Clone)]
+pub enum DivideDifference {
+    MissingMove(String),
+    IllegalMove(String),
+    CountMismatch { move_str: String, expected: usize, found: usize }
+}
+
+impl fmt::Display for DivideDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DivideDifference::MissingMove(ref m) => write!(f, "feldspar missing move: {}", m),
+            DivideDifference::IllegalMove(ref m) => write!(f, "feldspar generated illegal move: {}", m),
+            DivideDifference::CountMismatch { ref move_str, expected, found } =>
+                write!(f, "{}: expected {} nodes, feldspar found {}", move_str, expected, found)
+        }
+    }
+}
+
+pub fn compare_divides(reference: &DivideMap, feldspar: &DivideMap) -> Vec<DivideDifference> {
+    let mut differences = Vec::new();
+
+    let mut reference_moves: Vec<&String> = reference.keys().collect();
+    reference_moves.sort();
+
+    for m in reference_moves {
+        match feldspar.get(m) {
+            None => differences.push(DivideDifference::MissingMove(m.clone())),
+            Some(&found) => {
+                let expected = reference[m];
+                if found != expected {
+                    differences.push(DivideDifference::CountMismatch {
+                        move_str: m.clone(), expected: expected, found: found
+                    });
+                }
+            }
+        }
+    }
+
+    let mut feldspar_moves: Vec<&String> = feldspar.keys().collect();
+    feldspar_moves.sort();
+
+    for m in feldspar_moves {
+        if !reference.contains_key(m) {
+            differences.push(DivideDifference::IllegalMove(m.clone()));
+        }
+    }
+
+    return differences;
+}
+
+// Follows a move whose subtree counts disagree until it reaches a position
+// where the legal move lists themselves differ, and returns that position.
+// Returns None if everything agrees to the requested depth.
+pub fn perft_debug<F>(game: Game, depth: usize, reference_divide: &mut F)
+    -> Result<Option<(Game, Vec<DivideDifference>)>, String>
+    where F: FnMut(&Game, usize) -> Result<DivideMap, String>
+{
+    let reference = reference_divide(&game, depth)?;
+    let differences = compare_divides(&reference, &divide_map(game, depth));
+
+    if differences.is_empty() {
+        return Ok(None);
+    }
+
+    let move_lists_differ = differences.iter().any(|d| match *d {
+        DivideDifference::CountMismatch { .. } => false,
+        _ => true
+    });
+
+    if move_lists_differ || depth <= 1 {
+        return Ok(Some((game, differences)));
+    }
+
+    for d in differences.iter() {
+        if let DivideDifference::CountMismatch { ref move_str, .. } = *d {
+            let m = match move_from_algebraic(&game, move_str.clone()) {
+                Some(m) => m,
+                None => return Err(format!("couldn't play {} in {}", move_str, game.to_fen()))
+            };
+
+            let mut game_copy = game.clone();
+            game_copy.make_move(m);
+
+            if let Some(found) = perft_debug(game_copy, depth - 1, reference_divide)? {
+                return Ok(Some(found));
+            }
+        }
+    }
+
+    // the subtrees agree on their own, e.g. the reference read the position differently
+    return Ok(Some((game, differences)));
+}
+
+// any UCI engine that understands `go perft`, e.g. stockfish
+pub struct UCIPerftEngine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>
+}
+
+impl UCIPerftEngine {
+    pub fn spawn(path: &str) -> Result<UCIPerftEngine, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("couldn't start {}: {}", path, e))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        let mut engine = UCIPerftEngine { child: child, stdin: stdin, stdout: stdout };
+
+        engine.send("uci")?;
+        while engine.read_line()? != "uciok" {}
+
+        return Ok(engine);
+    }
+
+    pub fn divide(&mut self, game: &Game, depth: usize) -> Result<DivideMap, String> {
+        self.send(&format!("position fen {}", game.to_fen()))?;
+        self.send(&format!("go perft {}", depth))?;
+
+        let mut results = HashMap::new();
+
+        loop {
+            let line = self.read_line()?;
+
+            // the per-move counts are followed by a total, e.g. "Nodes searched: 197281"
+            if line.starts_with("Nodes") {
+                break;
+            }
+
+            let mut split = line.splitn(2, ':');
+            match (split.next(), split.next()) {
+                (Some(m), Some(count)) => match count.trim().parse::<usize>() {
+                    Ok(c) => { results.insert(m.trim().to_string(), c); },
+                    Err(_) => {}
+                },
+                _ => {}
+            }
+        }
+
+        return Ok(results);
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("couldn't write to reference engine: {}", e))
+    }
+
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.stdout.read_line(&mut line) {
+            Ok(0) => Err("reference engine closed its output".to_string()),
+            Ok(_) => Ok(line.trim().to_string()),
+            Err(e) => Err(format!("couldn't read from reference engine: {}", e))
+        }
+    }
+}
+
+impl Drop for UCIPerftEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
 
 #[cfg(test)]
 mod test {
     use perft::*;
 
+    #[test]
+    fn divide() {
+        let g = Game::starting_position();
+        let results = divide_map(g, 3);
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(results["e2e4"], 600);
+        assert_eq!(results["g1f3"], 440);
+        assert_eq!(results.values().sum::<usize>(), 8902);
+    }
+
+    fn check_hashed_node_count(positions: &[(&str, usize, usize)]) {
+        for &(fen, depth, nodes) in positions.iter() {
+            let g = Game::from_fen_str(fen).unwrap();
+            assert_eq!(perft_nodes(g, depth, &mut PerftTable::with_size_mb(16)), nodes);
+            // a tiny table keeps overwriting itself, but must not change the count
+            assert_eq!(perft_nodes(g, depth, &mut PerftTable::new(7)), nodes);
+        }
+    }
+
+    #[test]
+    fn hashed_node_count_shallow() {
+        check_hashed_node_count(&[
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 4, 197281),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3, 97862),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 3, 62379),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4, 43238)
+        ]);
+
+        let g = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let detailed = perft_parallel(g, 3, 1);
+        for depth in 1 .. 4 {
+            assert_eq!(perft_nodes(g, depth, &mut PerftTable::new(1024)), detailed.node_count[depth]);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn hashed_node_count_deep() {
+        check_hashed_node_count(&[
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 5, 4865609),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 4, 4085603),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 4, 2103487),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 6, 11030083)
+        ]);
+    }
+
+    fn check_perft_suite(max_depth: usize) {
+        let entries = parse_perft_suite(PERFT_SUITE).unwrap();
+        let failures = run_perft_suite(&entries, max_depth);
+
+        for failure in failures.iter() {
+            println!("{}", failure);
+        }
+
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn perft_suite_shallow() {
+        check_perft_suite(4);
+    }
+
+    #[test]
+    #[ignore]
+    fn perft_suite_deep() {
+        check_perft_suite(MAX_PERFT_DEPTH);
+    }
+
+    #[test]
+    fn perft_suite_parsing() {
+        assert_eq!(parse_perft_suite("# comment\n\n4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 66\n"),
+                   Ok(vec![PerftSuiteEntry {
+                       fen: "4k3/8/8/8/8/8/8/4K2R w K - 0 1".to_string(),
+                       expected: vec![(1, 15), (2, 66)]
+                   }]));
+
+        assert!(parse_perft_suite("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1").is_err());
+        assert!(parse_perft_suite("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;X1 15").is_err());
+        assert!(parse_perft_suite("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D0 1").is_err());
+        assert!(parse_perft_suite("4k3/8/8/8/8/8 w K - 0 1 ;D1 15").is_err());
+    }
+
+    #[test]
+    fn debug_narrows_to_differing_position() {
+        let start = Game::starting_position();
+        let mut after_e4 = start;
+        after_e4.make_move(move_from_algebraic(&start, "e2e4".to_string()).unwrap());
+
+        // a reference that agrees everywhere
+        assert_eq!(perft_debug(start, 3, &mut |g: &Game, d| Ok(divide_map(*g, d))), Ok(None));
+
+        // a reference that doesn't know about 1. e4 e5
+        let mut reference = |g: &Game, d| {
+            let mut results = divide_map(*g, d);
+            if g.to_fen() == after_e4.to_fen() {
+                results.remove("e7e5");
+            } else if g.to_fen() == start.to_fen() {
+                let e4 = results["e2e4"];
+                results.insert("e2e4".to_string(), e4 - divide_map(after_e4, d - 1)["e7e5"]);
+            }
+            Ok(results)
+        };
+
+        match perft_debug(start, 3, &mut reference) {
+            Ok(Some((g, differences))) => {
+                assert!(g == after_e4);
+                assert_eq!(differences, vec![DivideDifference::IllegalMove("e7e5".to_string())]);
+            },
+            x => panic!("unexpected debug result: {:?}", x.map(|r| r.map(|(g, _)| g.to_fen())))
+        }
+    }
+
     #[test]
     fn standard_position() {
         let mut correct_result = PerftResult::new();
@@ -397,7 +808,7 @@ mod test {
         correct_result.check_mates[6] = 10828;
 
         let g = Game::starting_position();
-        let result = perft(g, 6);
+        let result = perft(g, 6, num_cpus::get());
 
         assert!(result == correct_result);
     }
@@ -449,7 +860,7 @@ mod test {
         correct_result.check_mates[5] = 30171;
 
         let g = Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
-        let result = perft(g, 5);
+        let result = perft(g, 5, num_cpus::get());
 
         assert!(result == correct_result);
     }
@@ -465,7 +876,7 @@ mod test {
         correct_result.node_count[5] = 89941194;
 
         let g = Game::from_fen_str("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8").unwrap();
-        let result = perft(g, 5);
+        let result = perft(g, 5, num_cpus::get());
 
         assert!(result.node_count[1] == correct_result.node_count[1]);
         assert!(result.node_count[2] == correct_result.node_count[2]);
@@ -536,7 +947,7 @@ mod test {
         correct_result.check_mates[7] = 87;
 
         let g = Game::from_fen_str("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
-        let result = perft(g, 7);
+        let result = perft(g, 7, num_cpus::get());
 
         assert!(result == correct_result);
     }
@@ -0,0 +1,762 @@
+use core::*;
+use game::*;
+use moves::*;
+use san::*;
+
+use std::fmt;
+use std::io::{BufRead, Lines};
+
+// export format keeps lines shorter than 80 characters
+const PGN_LINE_WIDTH: usize = 79;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PgnError {
+    Io(String),
+    Syntax { line: usize, message: String },
+    InvalidFen { line: usize, fen: String },
+    IllegalMove { line: usize, san: String }
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PgnError::Io(ref e) => write!(f, "error reading PGN: {}", e),
+            PgnError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message),
+            PgnError::InvalidFen { line, ref fen } => write!(f, "line {}: invalid FEN tag: {}", line, fen),
+            PgnError::IllegalMove { line, ref san } => write!(f, "line {}: illegal move: {}", line, san)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PgnMove {
+    pub mv: Move,
+    // numeric annotation glyphs, `!` is stored as $1 and so on
+    pub nags: Vec<u8>,
+    // only used for the first move of a line, otherwise comments follow a move
+    pub comment_before: Option<String>,
+    pub comments: Vec<String>,
+    // alternatives to this move, each played from the position before it
+    pub variations: Vec<Vec<PgnMove>>
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> PgnMove {
+        PgnMove {
+            mv: m,
+            nags: Vec::new(),
+            comment_before: None,
+            comments: Vec::new(),
+            variations: Vec::new()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: Game,
+    pub moves: Vec<PgnMove>,
+    // 1-0, 0-1, 1/2-1/2 or *
+    pub result: String
+}
+
+impl PgnGame {
+    // a game with the seven tag roster filled in with unknowns
+    pub fn new(start: Game) -> PgnGame {
+        let mut pgn = PgnGame {
+            tags: Vec::new(),
+            start: start,
+            moves: Vec::new(),
+            result: "*".to_string()
+        };
+
+        for name in ["Event", "Site", "Date", "Round", "White", "Black"].iter() {
+            pgn.set_tag(name, "?");
+        }
+        pgn.set_tag("Result", "*");
+
+        if start != Game::starting_position() {
+            pgn.set_tag("SetUp", "1");
+            pgn.set_tag("FEN", &start.to_fen());
+        }
+
+        pgn
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|t| t.0 == name).map(|t| t.1.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter().position(|t| t.0 == name) {
+            Some(idx) => self.tags[idx].1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string()))
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+        self.set_tag("Result", result);
+    }
+
+    // adds a move to the end of the main line
+    pub fn push_move(&mut self, m: Move) {
+        self.moves.push(PgnMove::new(m));
+    }
+
+    pub fn mainline(&self) -> Vec<Move> {
+        self.moves.iter().map(|pm| pm.mv).collect()
+    }
+
+    pub fn to_pgn_string(&self) -> String {
+        let mut pgn = String::new();
+
+        for &(ref name, ref value) in self.tags.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+
+        if !self.tags.is_empty() {
+            pgn.push('\n');
+        }
+
+        let mut tokens = Vec::new();
+        write_line(&mut tokens, self.start, &self.moves);
+        tokens.push(self.result.clone());
+
+        pgn.push_str(&wrap_tokens(&tokens, PGN_LINE_WIDTH));
+        pgn.push_str("\n\n");
+
+        pgn
+    }
+}
+
+fn push_comment(tokens: &mut Vec<String>, comment: &str) {
+    // split into words so that long comments can be wrapped
+    let words: Vec<&str> = comment.split_whitespace().collect();
+
+    if words.is_empty() {
+        tokens.push("{}".to_string());
+        return;
+    }
+
+    for (idx, word) in words.iter().enumerate() {
+        let mut token = word.to_string();
+        if idx == 0 {
+            token.insert(0, '{');
+        }
+        if idx == words.len() - 1 {
+            token.push('}');
+        }
+        tokens.push(token);
+    }
+}
+
+fn write_line(tokens: &mut Vec<String>, start: Game, moves: &[PgnMove]) {
+    let mut game = start;
+    // black's moves get a number after anything that interrupts the line
+    let mut needs_number = true;
+
+    for pm in moves.iter() {
+        if let Some(ref comment) = pm.comment_before {
+            push_comment(tokens, comment);
+            needs_number = true;
+        }
+
+        if game.to_move == Color::White {
+            tokens.push(format!("{}.", game.fullmoves));
+        } else if needs_number {
+            tokens.push(format!("{}...", game.fullmoves));
+        }
+        needs_number = false;
+
+        tokens.push(move_to_san(&game, pm.mv));
+
+        for nag in pm.nags.iter() {
+            tokens.push(format!("${}", nag));
+        }
+
+        for comment in pm.comments.iter() {
+            push_comment(tokens, comment);
+            needs_number = true;
+        }
+
+        for variation in pm.variations.iter() {
+            tokens.push("(".to_string());
+            write_line(tokens, game, variation);
+            tokens.push(")".to_string());
+            needs_number = true;
+        }
+
+        game.make_move(pm.mv);
+    }
+}
+
+// Joins tokens with spaces, but none inside the parentheses around a
+// variation, and breaks lines before they grow past the given width.
+fn wrap_tokens(tokens: &[String], width: usize) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let mut open_paren = false;
+
+    for token in tokens.iter() {
+        if token == "(" {
+            open_paren = true;
+        } else if token == ")" && !words.is_empty() {
+            words.last_mut().unwrap().push(')');
+        } else if open_paren {
+            words.push(format!("({}", token));
+            open_paren = false;
+        } else {
+            words.push(token.clone());
+        }
+    }
+
+    let mut text = String::new();
+    let mut line_len = 0;
+
+    for word in words.iter() {
+        if line_len > 0 && line_len + 1 + word.len() > width {
+            text.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            text.push(' ');
+            line_len += 1;
+        }
+
+        text.push_str(word);
+        line_len += word.len();
+    }
+
+    text
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    Str(String),
+    Symbol(String),
+    Comment(String),
+    Nag(u8),
+    Result(String)
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_+#=:-/".contains(c)
+}
+
+// the traditional suffix annotations and their NAG equivalents
+fn suffix_nag(suffix: &str) -> Option<u8> {
+    match suffix {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None
+    }
+}
+
+// splits the text of one game into tokens, each tagged with its line number
+fn tokenize(text: &str, first_line: usize) -> Result<Vec<(Token, usize)>, PgnError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = first_line;
+    let mut idx = 0;
+
+    let syntax_error = |line: usize, message: &str| PgnError::Syntax { line: line, message: message.to_string() };
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        let token_line = line;
+
+        if c == '\n' {
+            line += 1;
+            idx += 1;
+            continue;
+        }
+
+        if c.is_whitespace() || c == '.' {
+            idx += 1;
+            continue;
+        }
+
+        // escaped lines and rest-of-line comments
+        if c == ';' || (c == '%' && (idx == 0 || chars[idx - 1] == '\n')) {
+            let start = idx + 1;
+            while idx < chars.len() && chars[idx] != '\n' {
+                idx += 1;
+            }
+
+            if c == ';' {
+                let comment: String = chars[start .. idx].iter().collect();
+                tokens.push((Token::Comment(comment.trim().to_string()), token_line));
+            }
+            continue;
+        }
+
+        match c {
+            '[' => { tokens.push((Token::LeftBracket, line)); idx += 1; },
+            ']' => { tokens.push((Token::RightBracket, line)); idx += 1; },
+            '(' => { tokens.push((Token::LeftParen, line)); idx += 1; },
+            ')' => { tokens.push((Token::RightParen, line)); idx += 1; },
+            '*' => { tokens.push((Token::Result("*".to_string()), line)); idx += 1; },
+            '{' => {
+                let mut comment = String::new();
+                idx += 1;
+                while idx < chars.len() && chars[idx] != '}' {
+                    if chars[idx] == '\n' {
+                        line += 1;
+                    }
+                    comment.push(chars[idx]);
+                    idx += 1;
+                }
+
+                if idx == chars.len() {
+                    return Err(syntax_error(token_line, "unterminated comment"));
+                }
+                idx += 1;
+
+                let comment: Vec<&str> = comment.split_whitespace().collect();
+                tokens.push((Token::Comment(comment.join(" ")), token_line));
+            },
+            '"' => {
+                let mut value = String::new();
+                idx += 1;
+                while idx < chars.len() && chars[idx] != '"' && chars[idx] != '\n' {
+                    if chars[idx] == '\\' && idx + 1 < chars.len() {
+                        idx += 1;
+                    }
+                    value.push(chars[idx]);
+                    idx += 1;
+                }
+
+                if idx == chars.len() || chars[idx] != '"' {
+                    return Err(syntax_error(token_line, "unterminated string"));
+                }
+                idx += 1;
+
+                tokens.push((Token::Str(value), token_line));
+            },
+            '$' => {
+                let start = idx + 1;
+                idx += 1;
+                while idx < chars.len() && chars[idx].is_ascii_digit() {
+                    idx += 1;
+                }
+
+                let digits: String = chars[start .. idx].iter().collect();
+                match digits.parse::<u8>() {
+                    Ok(nag) => tokens.push((Token::Nag(nag), token_line)),
+                    Err(_) => return Err(syntax_error(token_line, "invalid NAG"))
+                }
+            },
+            '!' | '?' => {
+                let start = idx;
+                while idx < chars.len() && (chars[idx] == '!' || chars[idx] == '?') {
+                    idx += 1;
+                }
+
+                let suffix: String = chars[start .. idx].iter().collect();
+                match suffix_nag(&suffix) {
+                    Some(nag) => tokens.push((Token::Nag(nag), token_line)),
+                    None => return Err(syntax_error(token_line, &format!("unknown annotation {}", suffix)))
+                }
+            },
+            c if c.is_ascii_alphanumeric() => {
+                let start = idx;
+                while idx < chars.len() && is_symbol_char(chars[idx]) {
+                    idx += 1;
+                }
+
+                let symbol: String = chars[start .. idx].iter().collect();
+
+                match symbol.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" => tokens.push((Token::Result(symbol), token_line)),
+                    // move numbers carry no information
+                    s if s.chars().all(|c| c.is_ascii_digit()) => {},
+                    _ => tokens.push((Token::Symbol(symbol), token_line))
+                }
+            },
+            c => return Err(syntax_error(token_line, &format!("unexpected character {}", c)))
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct PgnParser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    last_line: usize
+}
+
+impl PgnParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).map(|t| t.1).unwrap_or(self.last_line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|t| t.0.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn syntax_error(&self, message: &str) -> PgnError {
+        PgnError::Syntax { line: self.line(), message: message.to_string() }
+    }
+
+    fn parse_tag(&mut self) -> Result<(String, String), PgnError> {
+        self.next();
+
+        let name = match self.next() {
+            Some(Token::Symbol(name)) => name,
+            _ => { self.pos -= 1; return Err(self.syntax_error("expected a tag name")); }
+        };
+
+        let value = match self.next() {
+            Some(Token::Str(value)) => value,
+            _ => { self.pos -= 1; return Err(self.syntax_error("expected a quoted tag value")); }
+        };
+
+        match self.next() {
+            Some(Token::RightBracket) => Ok((name, value)),
+            _ => { self.pos -= 1; Err(self.syntax_error("expected ] after tag value")) }
+        }
+    }
+
+    // Reads moves until the end of the variation or game. Every move is
+    // checked against the legal moves of the position it is played in.
+    fn parse_line(&mut self, start: Game) -> Result<Vec<PgnMove>, PgnError> {
+        let mut moves: Vec<PgnMove> = Vec::new();
+        let mut game = start;
+        let mut before_last_move = start;
+        let mut leading_comments: Vec<String> = Vec::new();
+
+        loop {
+            let token = match self.peek() {
+                None | Some(&Token::RightParen) | Some(&Token::Result(_)) => break,
+                Some(token) => token.clone()
+            };
+
+            let line = self.line();
+
+            match token {
+                Token::Symbol(san) => {
+                    let m = match move_from_san(&game, &san) {
+                        Some(m) => m,
+                        None => return Err(PgnError::IllegalMove { line: line, san: san })
+                    };
+
+                    let mut pm = PgnMove::new(m);
+                    if !leading_comments.is_empty() {
+                        pm.comment_before = Some(leading_comments.join(" "));
+                        leading_comments.clear();
+                    }
+
+                    moves.push(pm);
+                    before_last_move = game;
+                    game.make_move(m);
+                    self.pos += 1;
+                },
+                Token::Nag(nag) => {
+                    match moves.last_mut() {
+                        Some(pm) => pm.nags.push(nag),
+                        None => return Err(self.syntax_error("annotation before any move"))
+                    }
+                    self.pos += 1;
+                },
+                Token::Comment(comment) => {
+                    match moves.last_mut() {
+                        Some(pm) => pm.comments.push(comment),
+                        None => leading_comments.push(comment)
+                    }
+                    self.pos += 1;
+                },
+                Token::LeftParen => {
+                    if moves.is_empty() {
+                        return Err(self.syntax_error("variation before any move"));
+                    }
+                    self.pos += 1;
+
+                    let variation = self.parse_line(before_last_move)?;
+
+                    match self.next() {
+                        Some(Token::RightParen) => {},
+                        _ => { self.pos -= 1; return Err(self.syntax_error("unterminated variation")); }
+                    }
+
+                    if !variation.is_empty() {
+                        moves.last_mut().unwrap().variations.push(variation);
+                    }
+                },
+                _ => return Err(self.syntax_error("unexpected token in movetext"))
+            }
+        }
+
+        Ok(moves)
+    }
+}
+
+// Parses a single game, the first line of which is `first_line` in the file.
+pub fn parse_pgn_game(text: &str, first_line: usize) -> Result<PgnGame, PgnError> {
+    let tokens = tokenize(text, first_line)?;
+    let last_line = tokens.last().map(|t| t.1).unwrap_or(first_line);
+
+    let mut parser = PgnParser { tokens: tokens, pos: 0, last_line: last_line };
+
+    let mut tags = Vec::new();
+    while parser.peek() == Some(&Token::LeftBracket) {
+        tags.push(parser.parse_tag()?);
+    }
+
+    let start = match tags.iter().find(|t| t.0 == "FEN") {
+        Some(&(_, ref fen)) => match Game::from_fen_str(fen) {
+            Some(g) => g,
+            None => return Err(PgnError::InvalidFen { line: first_line, fen: fen.clone() })
+        },
+        None => Game::starting_position()
+    };
+
+    let moves = parser.parse_line(start)?;
+
+    let result = match parser.next() {
+        Some(Token::Result(result)) => result,
+        Some(_) => { parser.pos -= 1; return Err(parser.syntax_error("unbalanced )")); },
+        None => tags.iter().find(|t| t.0 == "Result").map(|t| t.1.clone()).unwrap_or("*".to_string())
+    };
+
+    if parser.peek().is_some() {
+        return Err(parser.syntax_error("moves after the game result"));
+    }
+
+    Ok(PgnGame {
+        tags: tags,
+        start: start,
+        moves: moves,
+        result: result
+    })
+}
+
+// Reads games one at a time, so that large databases don't have to fit in
+// memory. A game that fails to parse gives an error, and reading carries on
+// with the next one.
+pub struct PgnReader<R: BufRead> {
+    lines: Lines<R>,
+    line_number: usize,
+    // the first line of the next game, read while looking for the end of the last one
+    pending: Option<String>
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> PgnReader<R> {
+        PgnReader {
+            lines: reader.lines(),
+            line_number: 0,
+            pending: None
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Result<PgnGame, PgnError>> {
+        let mut text = String::new();
+        let mut first_line = 0;
+        let mut seen_movetext = false;
+        let mut in_comment = false;
+
+        loop {
+            let line = match self.pending.take() {
+                Some(l) => l,
+                None => match self.lines.next() {
+                    Some(Ok(l)) => { self.line_number += 1; l },
+                    Some(Err(e)) => return Some(Err(PgnError::Io(e.to_string()))),
+                    None => break
+                }
+            };
+
+            let trimmed = line.trim();
+
+            if !in_comment {
+                // a tag after the movetext starts the next game
+                if seen_movetext && trimmed.starts_with('[') {
+                    self.pending = Some(line);
+                    break;
+                }
+
+                if text.is_empty() && trimmed.is_empty() {
+                    continue;
+                }
+
+                if !trimmed.is_empty() && !trimmed.starts_with('[') && !trimmed.starts_with('%') {
+                    seen_movetext = true;
+                }
+            }
+
+            if text.is_empty() {
+                first_line = self.line_number;
+            }
+
+            // track whether the line ends inside a brace comment
+            let mut in_string = false;
+            for c in line.chars() {
+                match c {
+                    '}' if in_comment => in_comment = false,
+                    '{' if !in_comment && !in_string => in_comment = true,
+                    '"' if !in_comment => in_string = !in_string,
+                    ';' if !in_comment && !in_string => break,
+                    _ => {}
+                }
+            }
+
+            text.push_str(&line);
+            text.push('\n');
+        }
+
+        if text.is_empty() {
+            return None;
+        }
+
+        Some(parse_pgn_game(&text, first_line))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pgn::*;
+    use zobrist::*;
+
+    fn read_pgn_str(pgn: &str) -> Result<Vec<PgnGame>, PgnError> {
+        PgnReader::new(pgn.as_bytes()).collect()
+    }
+
+    const ANNOTATED_GAME: &'static str = r#"
+[Event "Casual Game"]
+[Site "London"]
+[Date "1851.06.21"]
+[Round "?"]
+[White "Anderssen, Adolf"]
+[Black "Kieseritzky, Lionel"]
+[Result "1-0"]
+
+{The Immortal Game} 1. e4 e5 2. f4 exf4 3. Bc4 Qh4+ 4. Kf1 b5 $2 5. Bxb5 Nf6
+6. Nf3 Qh6 7. d3 Nh5 8. Nh4 Qg5 9. Nf5 c6 10. g4 Nf6 11. Rg1 cxb5 12. h4 Qg6
+13. h5 Qg5 14. Qf3 Ng8 15. Bxf4 Qf6 16. Nc3 Bc5 17. Nd5 Qxb2 18. Bd6 Bxg1
+(18... Qxa1+ 19. Ke2 Qb2 (19... Qxg1 20. Nc7+ {with mate to follow}) 20. Kd2)
+19. e5 Qxa1+ 20. Ke2 Na6 21. Nxg7+ Kd8 22. Qf6+ Nxf6 23. Be7# ; mate
+1-0
+"#;
+
+    #[test]
+    fn parsing() {
+        init_zobrist_hashing();
+
+        let game = parse_pgn_game(ANNOTATED_GAME, 1).unwrap();
+
+        assert_eq!(game.tag("White"), Some("Anderssen, Adolf"));
+        assert_eq!(game.tag("Opening"), None);
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.moves.len(), 45);
+
+        assert_eq!(game.moves[0].comment_before, Some("The Immortal Game".to_string()));
+        assert_eq!(game.moves[7].nags, vec![2]);
+        assert_eq!(game.moves[44].comments, vec!["mate".to_string()]);
+
+        let mut end = game.start;
+        for m in game.mainline() {
+            end.make_move(m);
+        }
+        assert_eq!(end.outcome, Some(GameResult::Win(Color::White)));
+
+        // 18... Bxg1 has one alternative, which itself has a nested one
+        let variations = &game.moves[35].variations;
+        assert_eq!(variations.len(), 1);
+        assert_eq!(variations[0].len(), 4);
+        assert_eq!(variations[0][2].variations[0].len(), 2);
+        assert_eq!(variations[0][2].variations[0][1].comments, vec!["with mate to follow".to_string()]);
+    }
+
+    #[test]
+    fn errors() {
+        let tags = "[Event \"?\"]\n\n";
+
+        let illegal = format!("{}1. e4 e5 2. Ke3 *", tags);
+        assert_eq!(parse_pgn_game(&illegal, 1), Err(PgnError::IllegalMove { line: 3, san: "Ke3".to_string() }));
+
+        let illegal_in_variation = format!("{}1. e4 (1. d4 d5 2. d5) e5 *", tags);
+        assert_eq!(parse_pgn_game(&illegal_in_variation, 1), Err(PgnError::IllegalMove { line: 3, san: "d5".to_string() }));
+
+        assert!(parse_pgn_game("1. e4 (1. d4 e5 *", 1).is_err());
+        assert!(parse_pgn_game("1. e4 e5) *", 1).is_err());
+        assert!(parse_pgn_game("1. e4 {unterminated *", 1).is_err());
+        assert!(parse_pgn_game("[Event \"?\" 1. e4 *", 1).is_err());
+        assert!(parse_pgn_game("[FEN \"not a fen\"]\n*", 1).is_err());
+        assert!(parse_pgn_game("1. e4 e5 1-0 2. Nf3", 1).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        init_zobrist_hashing();
+
+        let game = parse_pgn_game(ANNOTATED_GAME, 1).unwrap();
+        let written = game.to_pgn_string();
+        let unwrapped = written.replace('\n', " ");
+
+        assert!(written.lines().all(|l| l.len() <= PGN_LINE_WIDTH));
+        assert!(unwrapped.contains("4. Kf1 b5 $2 5. Bxb5"));
+        assert!(unwrapped.contains("18. Bd6 Bxg1 (18... Qxa1+ 19. Ke2 Qb2 (19... Qxg1 20. Nc7+ {with mate to follow}) 20. Kd2) 19. e5"));
+        assert_eq!(parse_pgn_game(&written, 1), Ok(game));
+
+        // a game set up from a position, starting with a black move
+        let start = Game::from_fen_str("4k3/8/8/8/8/8/4P3/4K3 b - - 0 40").unwrap();
+        let mut game = PgnGame::new(start);
+        game.push_move(move_from_san(&start, "Kd7").unwrap());
+        game.set_result("1/2-1/2");
+
+        let written = game.to_pgn_string();
+        assert!(written.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]"));
+        assert!(written.contains("40... Kd7 1/2-1/2"));
+        assert_eq!(parse_pgn_game(&written, 1), Ok(game));
+    }
+
+    #[test]
+    fn streaming() {
+        let database = "\
+[Event \"first\"]
+
+1. e4 e5 {a comment
+[that looks like a tag]} *
+
+[Event \"second\"]
+
+1. e4 e6 2. Ke3 *
+[Event \"third\"]
+1. d4 1/2-1/2
+";
+
+        let games: Vec<Result<PgnGame, PgnError>> = PgnReader::new(database.as_bytes()).collect();
+        assert_eq!(games.len(), 3);
+
+        let first = games[0].clone().unwrap();
+        assert_eq!(first.moves[1].comments, vec!["a comment [that looks like a tag]".to_string()]);
+
+        assert_eq!(games[1], Err(PgnError::IllegalMove { line: 8, san: "Ke3".to_string() }));
+
+        let third = games[2].clone().unwrap();
+        assert_eq!(third.tag("Event"), Some("third"));
+        assert_eq!(third.result, "1/2-1/2");
+
+        assert!(read_pgn_str(database).is_err());
+        assert_eq!(read_pgn_str("").unwrap().len(), 0);
+    }
+}
@@ -1,62 +1,475 @@
+use core::*;
+use eval::*;
 use game::*;
 use movegen::*;
+use moves::*;
+use pgn::*;
+use san::*;
 use search::*;
-use tree::*;
-use eval::*;
+use zobrist::*;
+
+use chrono::Local;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const DEFAULT_PLAY_MOVETIME_MS: u32 = 5000;
+const PLAY_HASH_MB: usize = 64;
+
+const PLAY_HELP: &'static str = "\
+Enter a move in coordinate (e2e4, e7e8q) or algebraic (e4, Nf3, O-O) notation, or one of:
+  undo         take back your last move
+  flip         turn the board around
+  hint         ask the engine for a move
+  fen          print the current position
+  save <file>  write the game so far to a PGN file
+  time <sec>   set the engine's time per move
+  new          start a new game
+  help         show this message
+  quit         leave the game";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PlayCommand {
+    Move(Move),
+    Undo,
+    Flip,
+    Hint,
+    Fen,
+    Save(String),
+    Time(u32),
+    New,
+    Help,
+    Quit
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PlayError {
+    IllegalMove(String),
+    InvalidTime(String),
+    MissingPath
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlayError::IllegalMove(ref m) => write!(f, "Illegal move or unknown command: {} (type `help` for a list of commands)", m),
+            PlayError::InvalidTime(ref t) => write!(f, "Invalid time per move: {}", t),
+            PlayError::MissingPath => write!(f, "Missing file name, try `save game.pgn`")
+        }
+    }
+}
+
+// Anything that isn't a command is tried as a coordinate move first and
+// then as SAN. Blank lines give Ok(None).
+pub fn parse_play_command(line: &str, game: &Game) -> Result<Option<PlayCommand>, PlayError> {
+    let mut args = line.split_whitespace();
+
+    let word = match args.next() {
+        Some(w) => w,
+        None => return Ok(None)
+    };
+
+    let cmd = match word {
+        "undo" => PlayCommand::Undo,
+        "flip" => PlayCommand::Flip,
+        "hint" => PlayCommand::Hint,
+        "fen" => PlayCommand::Fen,
+        "new" => PlayCommand::New,
+        "help" => PlayCommand::Help,
+        "quit" | "exit" => PlayCommand::Quit,
+        "save" => match args.next() {
+            Some(path) => PlayCommand::Save(path.to_string()),
+            None => return Err(PlayError::MissingPath)
+        },
+        "time" => {
+            let time_str = args.next().unwrap_or("");
+            match time_str.parse::<f32>() {
+                Ok(seconds) if seconds > 0.0 && seconds < 86400.0 => PlayCommand::Time((seconds * 1000.0) as u32),
+                _ => return Err(PlayError::InvalidTime(time_str.to_string()))
+            }
+        },
+        _ => {
+            let m = move_from_algebraic(game, word.to_string()).or_else(|| move_from_san(game, word));
+            match m {
+                Some(m) => PlayCommand::Move(m),
+                None => return Err(PlayError::IllegalMove(word.to_string()))
+            }
+        }
+    };
+
+    Ok(Some(cmd))
+}
+
+pub struct PlaySession {
+    start_game: Game,
+    game: Game,
+    // the position before each move of the game, for undo
+    undo_stack: Vec<Game>,
+    history: Vec<Hash>,
+    moves: Vec<Move>,
+    user_color: Color,
+    flipped: bool,
+    movetime_ms: u32,
+    context: SearchContext
+}
+
+impl PlaySession {
+    pub fn new(game: Game, user_color: Color, movetime_ms: u32) -> PlaySession {
+        let abort = Arc::new(AtomicBool::new(false));
+
+        PlaySession {
+            start_game: game,
+            game: game,
+            undo_stack: Vec::new(),
+            // repetitions of the starting position count too
+            history: vec![game.hash],
+            moves: Vec::new(),
+            user_color: user_color,
+            flipped: false,
+            movetime_ms: movetime_ms,
+            context: SearchContext::new(game, TranspositionTable::with_size_mb(PLAY_HASH_MB), abort)
+        }
+    }
+
+    // picks up a recorded game where its main line ends
+    pub fn from_pgn(pgn: &PgnGame, user_color: Color, movetime_ms: u32) -> PlaySession {
+        let mut session = PlaySession::new(pgn.start, user_color, movetime_ms);
+
+        for m in pgn.mainline() {
+            session.play_move(m);
+        }
+
+        session
+    }
+
+    // the game so far, with the players filled in
+    pub fn to_pgn(&self) -> PgnGame {
+        let mut pgn = PgnGame::new(self.start_game);
+
+        let (white, black) = match self.user_color {
+            Color::White => ("Player", "Feldspar"),
+            Color::Black => ("Feldspar", "Player")
+        };
+
+        pgn.set_tag("Event", "Feldspar play");
+        pgn.set_tag("Date", &Local::now().format("%Y.%m.%d").to_string());
+        pgn.set_tag("White", white);
+        pgn.set_tag("Black", black);
+
+        for m in self.moves.iter() {
+            pgn.push_move(*m);
+        }
+
+        pgn.set_result(self.game_result().map_or("*", |end| end.score_str()));
+        pgn
+    }
+
+    pub fn run(&mut self) {
+        println!("{}", PLAY_HELP);
+        println!("");
+        self.print_position();
+
+        let stdin = stdin();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            if self.game.to_move != self.user_color && self.game_result().is_none() {
+                self.engine_move();
+                self.print_position();
+                continue;
+            }
+
+            print!("{}> ", if self.game.to_move == Color::White { "white" } else { "black" });
+            let _ = stdout().flush();
+
+            let line = match lines.next() {
+                Some(Ok(l)) => l,
+                _ => break
+            };
+
+            match parse_play_command(&line, &self.game) {
+                Ok(Some(cmd)) => if !self.execute(cmd) { break; },
+                Ok(None) => {},
+                Err(e) => println!("{}", e)
+            }
+        }
+    }
+
+    // returns false once the user wants to quit
+    pub fn execute(&mut self, cmd: PlayCommand) -> bool {
+        match cmd {
+            PlayCommand::Move(m) => {
+                if self.game_result().is_some() {
+                    println!("The game is over, type `undo` or `new`");
+                } else {
+                    self.play_move(m);
+                    self.print_position();
+                }
+            },
+            PlayCommand::Undo => {
+                if self.take_back() {
+                    self.print_position();
+                } else {
+                    println!("Nothing to undo");
+                }
+            },
+            PlayCommand::Flip => {
+                self.flipped = !self.flipped;
+                self.print_position();
+            },
+            PlayCommand::Hint => {
+                match self.think() {
+                    Some((score, m)) => println!("Hint: {} ({})", move_to_san(&self.game, m), score_to_str(score)),
+                    None => println!("No legal moves")
+                }
+            },
+            PlayCommand::Fen => println!("{}", self.game.to_fen()),
+            PlayCommand::Save(path) => {
+                let written = File::create(&path).and_then(|mut f| f.write_all(self.to_pgn().to_pgn_string().as_bytes()));
+                match written {
+                    Ok(()) => println!("Saved the game to {}", path),
+                    Err(e) => println!("Couldn't write {}: {}", path, e)
+                }
+            },
+            PlayCommand::Time(ms) => {
+                self.movetime_ms = ms;
+                println!("Engine time per move: {:.1} s", ms as f32 / 1000.0);
+            },
+            PlayCommand::New => {
+                self.game = self.start_game;
+                self.undo_stack.clear();
+                self.history = vec![self.start_game.hash];
+                self.moves.clear();
+                self.flipped = false;
+                self.context.new_game();
+                self.print_position();
+            },
+            PlayCommand::Help => println!("{}", PLAY_HELP),
+            PlayCommand::Quit => return false
+        }
+
+        true
+    }
+
+    fn engine_move(&mut self) {
+        println!("Thinking...");
+
+        if let Some((score, m)) = self.think() {
+            println!("Feldspar plays {} ({})", move_to_san(&self.game, m), score_to_str(score));
+            self.play_move(m);
+        }
+    }
+
+    // iterative deepening on the current position until the time per move runs out
+    fn think(&mut self) -> Option<(Score, Move)> {
+        self.context.tree.reset_root(self.game, self.history.clone());
+        self.context.abort.store(false, Ordering::SeqCst);
+        self.context.timer = SearchTimer::new(self.movetime_ms);
+        self.context.limits = SearchLimits::new();
+
+        // no point looking any deeper once a mate is found
+        let result = iterative_deepening(&mut self.context, |_, _, score, _| score.mate_in().is_none());
+
+        if result.best_move.is_null() {
+            None
+        } else {
+            Some((result.score, result.best_move))
+        }
+    }
+
+    fn play_move(&mut self, m: Move) {
+        self.undo_stack.push(self.game);
+        self.game.make_move(m);
+        self.history.push(self.game.hash);
+        self.moves.push(m);
+    }
+
+    // takes back moves until it is the user's turn again
+    fn take_back(&mut self) -> bool {
+        if self.undo_stack.is_empty() {
+            return false;
+        }
+
+        loop {
+            match self.undo_stack.pop() {
+                Some(previous) => {
+                    self.game = previous;
+                    self.history.pop();
+                    self.moves.pop();
+                },
+                None => break
+            }
+
+            if self.game.to_move == self.user_color {
+                break;
+            }
+        }
+
+        true
+    }
+
+    fn print_position(&self) {
+        let perspective = if self.flipped { !self.user_color } else { self.user_color };
+
+        println!("");
+        self.game.board.print_from(perspective);
+        println!("");
+
+        if let Some(end) = self.game_result() {
+            println!("Game over: {}, {}", end.score_str(), end.reason());
+        } else if self.game.in_check() {
+            println!("Check!");
+        }
+    }
+
+    pub fn game_result(&self) -> Option<GameEnd> {
+        self.game.game_end(&self.history)
+    }
+}
+
+fn score_to_str(score: Score) -> String {
+    match score.mate_in() {
+        Some(moves) if moves > 0 => format!("mate in {}", moves),
+        Some(moves) => format!("mated in {}", -moves),
+        None => format!("{:+.2}", score.unwrap() as f32 / 100.0)
+    }
+}
+
+// asks which side to play until it gets an answer
+pub fn prompt_for_side() -> Option<Color> {
+    let stdin = stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("Play as (w)hite or (b)lack? ");
+        let _ = stdout().flush();
+
+        match lines.next() {
+            Some(Ok(l)) => match parse_side(&l) {
+                Some(color) => return Some(color),
+                None => println!("Please answer white or black")
+            },
+            _ => return None
+        }
+    }
+}
+
+pub fn parse_side(side: &str) -> Option<Color> {
+    match side.trim().to_lowercase().as_str() {
+        "w" | "white" => Some(Color::White),
+        "b" | "black" => Some(Color::Black),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use play::*;
+
+    #[test]
+    fn commands() {
+        let game = Game::starting_position();
+        let parse = |line: &str| parse_play_command(line, &game);
+
+        assert_eq!(parse(""), Ok(None));
+        assert_eq!(parse("undo"), Ok(Some(PlayCommand::Undo)));
+        assert_eq!(parse("  flip "), Ok(Some(PlayCommand::Flip)));
+        assert_eq!(parse("time 2.5"), Ok(Some(PlayCommand::Time(2500))));
+        assert_eq!(parse("time"), Err(PlayError::InvalidTime("".to_string())));
+        assert_eq!(parse("time -1"), Err(PlayError::InvalidTime("-1".to_string())));
+        assert_eq!(parse("save game.pgn"), Ok(Some(PlayCommand::Save("game.pgn".to_string()))));
+        assert_eq!(parse("save"), Err(PlayError::MissingPath));
+
+        let e2e4 = move_from_algebraic(&game, "e2e4".to_string()).unwrap();
+        assert_eq!(parse("e2e4"), Ok(Some(PlayCommand::Move(e2e4))));
+        assert_eq!(parse("e4"), Ok(Some(PlayCommand::Move(e2e4))));
+        assert_eq!(parse("e5"), Err(PlayError::IllegalMove("e5".to_string())));
+        assert_eq!(parse("resign"), Err(PlayError::IllegalMove("resign".to_string())));
+
+        assert_eq!(parse_side("White"), Some(Color::White));
+        assert_eq!(parse_side("b"), Some(Color::Black));
+        assert_eq!(parse_side("red"), None);
+    }
+
+    #[test]
+    fn undo_and_result() {
+        init_zobrist_hashing();
+
+        let mut session = PlaySession::new(Game::starting_position(), Color::White, 100);
+
+        for m in ["f3", "e5", "g4"].iter() {
+            let cmd = parse_play_command(m, &session.game).unwrap().unwrap();
+            session.execute(cmd);
+        }
+        assert!(session.game_result().is_none());
+
+        // the engine finds the mate
+        let (score, reply) = session.think().unwrap();
+        assert_eq!(score.mate_in(), Some(1));
+        session.play_move(reply);
+        assert_eq!(session.game_result(), Some(GameEnd::Checkmate(Color::Black)));
+
+        // back to the position after 1. f3 e5
+        assert!(session.take_back());
+        assert_eq!(session.game.to_move, Color::White);
+        assert_eq!(session.undo_stack.len(), 2);
+
+        session.execute(PlayCommand::Flip);
+        session.execute(PlayCommand::New);
+        assert_eq!(session.game, Game::starting_position());
+        assert!(!session.flipped);
+        assert!(!session.take_back());
+    }
+
+    #[test]
+    fn repetition_of_start() {
+        init_zobrist_hashing();
+
+        let mut session = PlaySession::new(Game::starting_position(), Color::Black, 100);
+
+        // the starting position comes up for the third time with the last move
+        for m in ["Nf3", "Nf6", "Ng1", "Ng8", "Nf3", "Nf6", "Ng1"].iter() {
+            let cmd = parse_play_command(m, &session.game).unwrap().unwrap();
+            session.execute(cmd);
+        }
+        assert!(session.game_result().is_none());
+
+        session.execute(PlayCommand::Move(move_from_algebraic(&session.game, "f6g8".to_string()).unwrap()));
+        assert_eq!(session.game_result(), Some(GameEnd::Repetition));
+
+        // taking back keeps the starting position in the history
+        assert!(session.take_back());
+        session.execute(PlayCommand::Move(move_from_algebraic(&session.game, "f6g8".to_string()).unwrap()));
+        assert_eq!(session.game_result(), Some(GameEnd::Repetition));
+    }
+
+    #[test]
+    fn pgn_round_trip() {
+        init_zobrist_hashing();
+
+        let mut session = PlaySession::new(Game::starting_position(), Color::Black, 100);
+        for m in ["f3", "e5", "g4", "Qh4#"].iter() {
+            let cmd = parse_play_command(m, &session.game).unwrap().unwrap();
+            session.execute(cmd);
+        }
+
+        let pgn = session.to_pgn();
+        assert_eq!(pgn.tag("White"), Some("Feldspar"));
+        assert_eq!(pgn.result, "0-1");
+
+        let text = pgn.to_pgn_string();
+        assert!(text.ends_with("1. f3 e5 2. g4 Qh4# 0-1\n\n"));
 
-// pub fn play_against_ai() {
-//     // let mut tree = SearchTree::new(Game::from_fen_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap());
-//     let mut tree = SearchTree::new(Game::starting_position());
-//
-//     loop {
-//         tree.focus().board.print();
-//         println!("FEN: {}", tree.focus().to_fen());
-//         println!("score: {}", Score::recompute(&tree.focus()).val);
-//         println!("");
-//         print!("Enter your move: ");
-//
-//         use std::io::{stdin,stdout,Write};
-//         let _=stdout().flush();
-//         let mut s=String::new();
-//         stdin().read_line(&mut s).expect("Did not enter a correct string");
-//
-//         if let Some('\n')=s.chars().next_back() {
-//             s.pop();
-//         }
-//         if let Some('\r')=s.chars().next_back() {
-//             s.pop();
-//         }
-//
-//         match move_from_algebraic(&tree.focus(), s) {
-//             Some(m) => {
-//                 tree.make_move(m);
-//                 let game_copy = *tree.focus();
-//                 tree.reset_root(game_copy, m);
-//                 let (_, ai_move) = alpha_beta(&mut tree,6);
-//                 tree.make_move(ai_move);
-//             },
-//             None => println!("Invalid move! Try again...")
-//         }
-//     }
-//
-// }
-
-// use rand::{thread_rng, ThreadRng, Rng};
-
-// pub struct MCTS {
-//     move_gen: MoveGen,
-//     rng: ThreadRng,
-//     move_buffer: MoveBuffer
-// }
-//
-// impl MCTS {
-//     pub fn new() -> MCTS {
-//         MCTS {
-//             move_gen: MoveGen::new(),
-//             rng: thread_rng(),
-//             move_buffer: alloc_move_buffer()
-//         }
-//     }
-//
-// }
+        let loaded = PgnReader::new(text.as_bytes()).next().unwrap().unwrap();
+        let mut resumed = PlaySession::from_pgn(&loaded, Color::White, 100);
+        assert_eq!(resumed.game, session.game);
+        assert_eq!(resumed.game_result(), Some(GameEnd::Checkmate(Color::Black)));
 
+        // undo goes back into the loaded moves
+        assert!(resumed.take_back());
+        assert_eq!(resumed.moves.len(), 2);
+    }
+}
@@ -54,6 +54,11 @@ impl Bitboard {
 
 impl Board {
     pub fn print(&self) {
+        self.print_from(Color::White);
+    }
+
+    // prints the board with the given side at the bottom
+    pub fn print_from(&self, perspective: Color) {
         let mut chars = vec!["  "; 64];
         for i in 0 .. 64 {
             match self.piece_at(Square::new(i)) {
@@ -62,10 +67,18 @@ impl Board {
             }
         }
 
-        let mut row_idx = 8;
+        let mut row_labels: Vec<u32> = (1 .. 9).rev().collect();
+        let mut file_labels = "   a b c d e f g h";
+
+        if perspective == Color::Black {
+            chars.reverse();
+            row_labels.reverse();
+            file_labels = "   h g f e d c b a";
+        }
+
         let mut bkg_color = "black";
-        eprintln!("   a b c d e f g h");
-        for row in chars.chunks(8) {
+        eprintln!("{}", file_labels);
+        for (row, row_idx) in chars.chunks(8).zip(row_labels) {
             match bkg_color {
                 "blue" => bkg_color = "white",
                 _ => bkg_color = "blue"
@@ -79,10 +92,9 @@ impl Board {
                 }
             }
             eprint!(" {}", row_idx.to_string());
-            row_idx -= 1;
             eprintln!();
         }
-        eprintln!("   a b c d e f g h");
+        eprintln!("{}", file_labels);
     }
 }
 
@@ -90,6 +102,6 @@ impl Move {
     //TODO: expand to print details (capture, ep, promotion, etc)
     //TODO: UCI print output vs. normal print different functions
     pub fn print(&self) {
-        eprintln!("{}{}", self.from().to_algebraic(), self.to().to_algebraic());
+        eprintln!("{}", self.to_uci_str());
     }
 }
@@ -0,0 +1,317 @@
+use core::*;
+use game::*;
+use move_list::*;
+use movegen::*;
+use moves::*;
+
+fn piece_from_char(c: char) -> Option<PieceType> {
+    match c {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None
+    }
+}
+
+fn piece_to_char(ptype: PieceType) -> char {
+    match ptype {
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+        PieceType::Pawn => 'P'
+    }
+}
+
+fn file_from_char(c: char) -> Option<u32> {
+    match c {
+        'a' ..= 'h' => Some(c as u32 - 'a' as u32 + 1),
+        _ => None
+    }
+}
+
+fn rank_from_char(c: char) -> Option<u32> {
+    match c {
+        '1' ..= '8' => Some(c as u32 - '0' as u32),
+        _ => None
+    }
+}
+
+// Formats a legal move in standard algebraic notation, e.g. `Nbd7`, `exd5`,
+// `O-O`, `e8=Q+` or `Qh4#`.
+pub fn move_to_san(game: &Game, m: Move) -> String {
+    let mut san = match m.flag() {
+        KING_CASTLE_FLAG => "O-O".to_string(),
+        QUEEN_CASTLE_FLAG => "O-O-O".to_string(),
+        _ => {
+            let mut san = String::new();
+
+            if m.moved_piece() == PieceType::Pawn {
+                if m.is_capture() {
+                    san.push_str(&m.from().to_algebraic()[..1]);
+                }
+            } else {
+                san.push(piece_to_char(m.moved_piece()));
+
+                // other pieces of the same type that could also go there
+                let legal_moves = next_moves_standalone(game);
+                let rivals: Vec<&Move> = legal_moves.iter()
+                    .filter(|o| o.moved_piece() == m.moved_piece() && o.to() == m.to() && o.from() != m.from())
+                    .collect();
+
+                if !rivals.is_empty() {
+                    let from_str = m.from().to_algebraic();
+                    if rivals.iter().all(|o| o.from().file() != m.from().file()) {
+                        san.push_str(&from_str[..1]);
+                    } else if rivals.iter().all(|o| o.from().rank() != m.from().rank()) {
+                        san.push_str(&from_str[1..]);
+                    } else {
+                        san.push_str(&from_str);
+                    }
+                }
+            }
+
+            if m.is_capture() {
+                san.push('x');
+            }
+
+            san.push_str(&m.to().to_algebraic());
+
+            if let Some(ptype) = m.promoted_piece() {
+                san.push('=');
+                san.push(piece_to_char(ptype));
+            }
+
+            san
+        }
+    };
+
+    let mut after = *game;
+    after.make_move(m);
+
+    match after.outcome {
+        Some(GameResult::Win(_)) => san.push('#'),
+        _ => if after.in_check() { san.push('+'); }
+    }
+
+    return san;
+}
+
+// Formats a line of moves, e.g. a principal variation, starting from the given position.
+pub fn moves_to_san(game: &Game, moves: &[Move]) -> Vec<String> {
+    let mut game = *game;
+
+    moves.iter().map(|m| {
+        let san = move_to_san(&game, *m);
+        game.make_move(*m);
+        san
+    }).collect()
+}
+
+// Matches the remaining characters, destination square last, against the
+// legal moves of one piece type.
+fn resolve_san(legal_moves: &MoveList, moved_piece: PieceType, chars: &[char]) -> Option<Move> {
+    let mut chars = chars.to_vec();
+
+    let mut promoted_piece = None;
+    if moved_piece == PieceType::Pawn && chars.len() >= 3 {
+        let last = chars[chars.len() - 1].to_ascii_uppercase();
+        if let Some(ptype) = piece_from_char(last) {
+            promoted_piece = Some(ptype);
+            chars.pop();
+        }
+    }
+
+    if chars.len() < 2 {
+        return None;
+    }
+
+    let to_rank = rank_from_char(chars.pop().unwrap())?;
+    let to_file = file_from_char(chars.pop().unwrap())?;
+
+    // whatever is left disambiguates the origin square
+    let mut from_file = None;
+    let mut from_rank = None;
+
+    for c in chars.iter() {
+        if let Some(f) = file_from_char(*c) {
+            from_file = Some(f);
+        } else if let Some(r) = rank_from_char(*c) {
+            from_rank = Some(r);
+        } else {
+            return None;
+        }
+    }
+
+    let mut matching = legal_moves.iter().filter(|m| {
+        m.moved_piece() == moved_piece
+            && m.to().file() == to_file
+            && m.to().rank() == to_rank
+            && from_file.map_or(true, |f| m.from().file() == f)
+            && from_rank.map_or(true, |r| m.from().rank() == r)
+            && m.promoted_piece() == promoted_piece
+    });
+
+    match (matching.next(), matching.next()) {
+        (Some(m), None) => Some(*m),
+        _ => None
+    }
+}
+
+// Resolves a move in algebraic notation against the legal moves. Beyond
+// strict SAN it accepts lowercase piece letters, `0-0` castling, long
+// algebraic forms like `Ng1-f3` or `e2e4`, `:` for captures and `e.p.`, and
+// ignores check and annotation marks. Returns None unless exactly one legal
+// move matches.
+pub fn move_from_san(game: &Game, san: &str) -> Option<Move> {
+    let mut san = san.trim().trim_end_matches(|c| c == '+' || c == '#' || c == '!' || c == '?');
+
+    for suffix in ["e.p.", "ep"].iter() {
+        if san.ends_with(suffix) {
+            san = san[.. san.len() - suffix.len()].trim_end();
+        }
+    }
+
+    let legal_moves = next_moves_standalone(game);
+
+    let castle_flag = match san.to_uppercase().replace('0', "O").as_str() {
+        "O-O" | "OO" => Some(KING_CASTLE_FLAG),
+        "O-O-O" | "OOO" => Some(QUEEN_CASTLE_FLAG),
+        _ => None
+    };
+
+    if let Some(flag) = castle_flag {
+        return legal_moves.iter().find(|m| m.flag() == flag).map(|m| *m);
+    }
+
+    let chars: Vec<char> = san.chars()
+        .filter(|c| *c != 'x' && *c != ':' && *c != '-' && *c != '=')
+        .collect();
+
+    let first = *chars.first()?;
+
+    match first {
+        'P' => resolve_san(&legal_moves, PieceType::Pawn, &chars[1..]),
+        // a lowercase b is a pawn on the b-file unless that makes no sense
+        'b' => resolve_san(&legal_moves, PieceType::Pawn, &chars)
+            .or_else(|| resolve_san(&legal_moves, PieceType::Bishop, &chars[1..])),
+        _ => match piece_from_char(first.to_ascii_uppercase()) {
+            Some(ptype) => resolve_san(&legal_moves, ptype, &chars[1..]),
+            None => resolve_san(&legal_moves, PieceType::Pawn, &chars)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use san::*;
+
+    fn san_to_uci(fen: &str, san: &str) -> Option<String> {
+        let game = Game::from_fen_str(fen).unwrap();
+        move_from_san(&game, san).map(|m| m.to_uci_str())
+    }
+
+    #[test]
+    fn parsing() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san_to_uci(start, "e4"), Some("e2e4".to_string()));
+        assert_eq!(san_to_uci(start, "Nf3"), Some("g1f3".to_string()));
+        assert_eq!(san_to_uci(start, "Nf3+!?"), Some("g1f3".to_string()));
+        assert_eq!(san_to_uci(start, "e5"), None);
+        assert_eq!(san_to_uci(start, "Ke2"), None);
+        assert_eq!(san_to_uci(start, ""), None);
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san_to_uci(kiwipete, "O-O"), Some("e1g1".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "O-O-O"), Some("e1c1".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "Bxa6"), Some("e2a6".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "dxe6"), Some("d5e6".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "gxh3"), Some("g2h3".to_string()));
+
+        // both knights reach d5, only one reaches b1
+        let knights = "4k3/8/8/8/8/2N1N3/8/4K3 w - - 0 1";
+        assert_eq!(san_to_uci(knights, "Nd5"), None);
+        assert_eq!(san_to_uci(knights, "Ncd5"), Some("c3d5".to_string()));
+        assert_eq!(san_to_uci(knights, "Ned5"), Some("e3d5".to_string()));
+        assert_eq!(san_to_uci(knights, "Nb1"), Some("c3b1".to_string()));
+
+        let promotion = "8/P6k/8/8/8/8/8/K7 w - - 0 1";
+        assert_eq!(san_to_uci(promotion, "a8=Q"), Some("a7a8q".to_string()));
+        assert_eq!(san_to_uci(promotion, "a8N"), Some("a7a8n".to_string()));
+        assert_eq!(san_to_uci(promotion, "a8"), None);
+    }
+
+    #[test]
+    fn forgiving_parsing() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san_to_uci(start, "nf3"), Some("g1f3".to_string()));
+        assert_eq!(san_to_uci(start, "Ng1-f3"), Some("g1f3".to_string()));
+        assert_eq!(san_to_uci(start, "e2-e4"), Some("e2e4".to_string()));
+        assert_eq!(san_to_uci(start, "e2e4"), Some("e2e4".to_string()));
+        assert_eq!(san_to_uci(start, "Pe4"), Some("e2e4".to_string()));
+        assert_eq!(san_to_uci(start, " e4 "), Some("e2e4".to_string()));
+        assert_eq!(san_to_uci(start, "b4"), Some("b2b4".to_string()));
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san_to_uci(kiwipete, "0-0-0"), Some("e1c1".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "o-o"), Some("e1g1".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "bxa6"), Some("e2a6".to_string()));
+        assert_eq!(san_to_uci(kiwipete, "d5:e6"), Some("d5e6".to_string()));
+
+        let en_passant = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(san_to_uci(en_passant, "exd6 e.p."), Some("e5d6".to_string()));
+        assert_eq!(san_to_uci(en_passant, "exd6ep"), Some("e5d6".to_string()));
+
+        let promotion = "8/P6k/8/8/8/8/8/K7 w - - 0 1";
+        assert_eq!(san_to_uci(promotion, "a8=q"), Some("a7a8q".to_string()));
+        assert_eq!(san_to_uci(promotion, "a7a8=R"), Some("a7a8r".to_string()));
+    }
+
+    #[test]
+    fn formatting() {
+        let to_san = |fen: &str, uci: &str| {
+            let game = Game::from_fen_str(fen).unwrap();
+            let m = move_from_algebraic(&game, uci.to_string()).unwrap();
+            let san = move_to_san(&game, m);
+            assert_eq!(move_from_san(&game, &san), Some(m));
+            san
+        };
+
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(to_san(start, "e2e4"), "e4");
+        assert_eq!(to_san(start, "g1f3"), "Nf3");
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(to_san(kiwipete, "e1g1"), "O-O");
+        assert_eq!(to_san(kiwipete, "e1c1"), "O-O-O");
+        assert_eq!(to_san(kiwipete, "d5e6"), "dxe6");
+        assert_eq!(to_san(kiwipete, "e5f7"), "Nxf7");
+        assert_eq!(to_san(kiwipete, "a1b1"), "Rb1");
+
+        // file, rank and full square disambiguation
+        assert_eq!(to_san("4k3/8/8/8/8/2N1N3/8/4K3 w - - 0 1", "c3d5"), "Ncd5");
+        assert_eq!(to_san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3"), "R1a3");
+        assert_eq!(to_san("4k3/8/8/8/Q1Q5/8/Q7/4K3 w - - 0 1", "a4b3"), "Qa4b3");
+
+        // a pinned knight doesn't need to be told apart
+        assert_eq!(to_san("4k3/4r3/8/8/8/2N1N3/8/4K3 w - - 0 1", "c3d5"), "Nd5");
+
+        assert_eq!(to_san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(to_san("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), "a8=Q+");
+        assert_eq!(to_san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8n"), "b8=N");
+        assert_eq!(to_san("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2", "d8h4"), "Qh4#");
+
+        let game = Game::starting_position();
+        let line: Vec<Move> = ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"].iter()
+            .scan(game, |g, uci| {
+                let m = move_from_algebraic(g, uci.to_string()).unwrap();
+                g.make_move(m);
+                Some(m)
+            })
+            .collect();
+        assert_eq!(moves_to_san(&game, &line), vec!["e4", "e5", "Nf3", "Nc6", "Bb5"]);
+    }
+}
@@ -1,72 +1,313 @@
 use core::*;
 use game::*;
+use logging::*;
 use movegen::*;
 use moves::*;
 use tree::*;
 use eval::*;
 use zobrist::*;
 
+use std::cmp::{max, min};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// transposition table entries only have six bits for the depth
+pub const MAX_SEARCH_DEPTH: u8 = 64;
+
+// root moves are only announced once the search has run this long
+const CURRMOVE_DELAY_MS: u64 = 1000;
+
+// null move pruning is only tried with at least this much depth left, and
+// cutoffs this deep are verified
+const NULL_MOVE_MIN_DEPTH: u8 = 2;
+const NULL_MOVE_VERIFICATION_DEPTH: u8 = 5;
+
+// quiet moves this late in the move list are searched with reduced depth
+const LMR_MIN_DEPTH: u8 = 3;
+const LMR_MIN_MOVES: usize = 3;
+const LMR_TABLE_MOVES: usize = 64;
+
+// Quiet moves are ordered by the killer slots for their ply, then the
+// countermove to the previous move, then the history table. History scores
+// are halved once one of them passes HISTORY_MAX, so they stay below both.
+const KILLER_SCORE: i32 = 1 << 22;
+const COUNTERMOVE_SCORE: i32 = KILLER_SCORE - 2;
+const HISTORY_MAX: i32 = 1 << 20;
+
+// the first aspiration window is this many centipawns either side of the last score
+const ASPIRATION_WINDOW: i16 = 25;
+const ASPIRATION_MIN_DEPTH: u8 = 4;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchLimits {
+    pub wtime: Option<u32>,
+    pub btime: Option<u32>,
+    pub winc: u32,
+    pub binc: u32,
+    pub movestogo: Option<u32>,
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<u32>,
+    pub mate: Option<u32>,
+    pub infinite: bool,
+    pub ponder: bool,
+    pub searchmoves: Vec<Move>
+}
+
+impl SearchLimits {
+    pub fn new() -> SearchLimits {
+        SearchLimits {
+            wtime: None,
+            btime: None,
+            winc: 0,
+            binc: 0,
+            movestogo: None,
+            depth: None,
+            nodes: None,
+            movetime: None,
+            mate: None,
+            infinite: false,
+            ponder: false,
+            searchmoves: Vec::new()
+        }
+    }
+
+    // deepest iteration allowed by the depth and mate limits
+    pub fn max_depth(&self) -> u8 {
+        let mut max_depth = MAX_SEARCH_DEPTH - 1;
+
+        if let Some(depth) = self.depth {
+            max_depth = min(max_depth, depth);
+        }
+
+        if let Some(moves) = self.mate {
+            // a mate in N moves is delivered at ply 2N - 1
+            let mate_plies = 2 * max(moves, 1) as usize - 1;
+            max_depth = min(max_depth as usize, mate_plies) as u8;
+        }
+
+        return max(max_depth, 1);
+    }
+}
+
 pub struct SearchContext {
     pub tree: SearchTree,
     pub qtree: SearchTree,
     pub table: TranspositionTable,
     pub timer: SearchTimer,
-    pub ran_out_of_time: bool
+    pub limits: SearchLimits,
+    pub excluded_root_moves: Vec<Move>,
+    pub nodes: u64,
+    pub seldepth: usize,
+    pub report_currmove: bool,
+    // time to allow once the opponent plays the move we are pondering on
+    pub ponder_time: Option<u32>,
+    // shared with the protocol thread, which sets it to interrupt a running search
+    pub abort: Arc<AtomicBool>,
+    // shared with the protocol thread, which clears it on `ponderhit`
+    pub pondering: Arc<AtomicBool>,
+    // turned off while verifying a null move cutoff
+    pub null_moves_allowed: bool,
+    // late move reductions, indexed by depth left and move number
+    pub reductions: [[u8; LMR_TABLE_MOVES]; MAX_SEARCH_DEPTH as usize],
+    // the last two quiet moves to cause a beta cutoff at each ply
+    pub killers: [[Move; 2]; MAX_SEARCH_DEPTH as usize],
+    // quiet beta cutoffs weighted by depth, indexed by side, from and to square
+    pub history: [[[i32; 64]; 64]; 2],
+    // the quiet move that last refuted each move, indexed by its from and to square
+    pub countermoves: [[Move; 64]; 64]
+}
+
+impl SearchContext {
+    pub fn new(game: Game, table: TranspositionTable, abort: Arc<AtomicBool>) -> SearchContext {
+        let mut new_qtree = SearchTree::new(game);
+        new_qtree.in_quiescence = true;
+
+        SearchContext {
+            tree: SearchTree::new(game),
+            qtree: new_qtree,
+            table: table,
+            timer: SearchTimer::new(u32::max_value()),
+            limits: SearchLimits::new(),
+            excluded_root_moves: Vec::new(),
+            nodes: 0,
+            seldepth: 0,
+            report_currmove: false,
+            ponder_time: None,
+            abort: abort,
+            pondering: Arc::new(AtomicBool::new(false)),
+            null_moves_allowed: true,
+            reductions: reduction_table(),
+            killers: [[Move::null(); 2]; MAX_SEARCH_DEPTH as usize],
+            history: [[[0; 64]; 64]; 2],
+            countermoves: [[Move::null(); 64]; 64]
+        }
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.abort.load(Ordering::Relaxed)
+    }
+
+    pub fn pondering(&self) -> bool {
+        self.pondering.load(Ordering::Relaxed)
+    }
+
+    // searchmoves and MultiPV both restrict which moves are searched at the root
+    pub fn root_move_allowed(&self, m: Move) -> bool {
+        if !self.limits.searchmoves.is_empty() && !self.limits.searchmoves.contains(&m) {
+            return false;
+        }
+
+        !self.excluded_root_moves.contains(&m)
+    }
+
+    // nothing learned in earlier searches carries over to the next game
+    pub fn new_game(&mut self) {
+        self.table.reset();
+        self.clear_move_ordering();
+    }
+
+    // forget the move ordering statistics gathered in previous games
+    pub fn clear_move_ordering(&mut self) {
+        self.killers = [[Move::null(); 2]; MAX_SEARCH_DEPTH as usize];
+        self.history = [[[0; 64]; 64]; 2];
+        self.countermoves = [[Move::null(); 64]; 64];
+    }
+
+    pub fn is_killer(&self, ply: usize, m: Move) -> bool {
+        ply < self.killers.len() && self.killers[ply].contains(&m)
+    }
+
+    // the refutation of the move that led to the current position, if any
+    pub fn countermove(&self) -> Move {
+        match self.tree.current_line.last() {
+            Some(prev) if !prev.is_null() => self.countermoves[prev.from().idx()][prev.to().idx()],
+            _ => Move::null()
+        }
+    }
+
+    fn quiet_move_score(&self, ply: usize, countermove: Move, m: Move) -> i32 {
+        if ply < self.killers.len() {
+            if m == self.killers[ply][0] {
+                return KILLER_SCORE;
+            } else if m == self.killers[ply][1] {
+                return KILLER_SCORE - 1;
+            }
+        }
+
+        if m == countermove {
+            return COUNTERMOVE_SCORE;
+        }
+
+        let color = self.tree.focus().to_move;
+        self.history[color as usize][m.from().idx()][m.to().idx()]
+    }
+
+    // a quiet move caused a beta cutoff
+    fn update_quiet_move_stats(&mut self, ply: usize, m: Move, depth_left: u8) {
+        if ply < self.killers.len() && self.killers[ply][0] != m {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = m;
+        }
+
+        if let Some(prev) = self.tree.current_line.last().cloned() {
+            if !prev.is_null() {
+                self.countermoves[prev.from().idx()][prev.to().idx()] = m;
+            }
+        }
+
+        let color = self.tree.focus().to_move as usize;
+        let entry = &mut self.history[color][m.from().idx()][m.to().idx()];
+        *entry += depth_left as i32 * depth_left as i32;
+
+        if *entry > HISTORY_MAX {
+            for from in self.history[color].iter_mut() {
+                for score in from.iter_mut() {
+                    *score /= 2;
+                }
+            }
+        }
+    }
+
+    // raises the abort flag once the time or node budget is spent
+    pub fn check_limits(&mut self) {
+        // on ponderhit the search carries on, but now on our own clock
+        if self.ponder_time.is_some() && !self.pondering() {
+            let remaining = self.ponder_time.take().unwrap();
+            self.timer.set_remaining(remaining);
+        }
+
+        // a ponder or infinite search only ends on stop, the limits apply from ponderhit
+        if self.ponder_time.is_some() || self.limits.infinite {
+            return;
+        }
+
+        let out_of_nodes = match self.limits.nodes {
+            Some(n) => self.nodes >= n,
+            None => false
+        };
+
+        if out_of_nodes || self.timer.finished() {
+            self.abort.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// Reductions grow with the log of both the depth left and the move number,
+// and always leave at least one ply to search.
+fn reduction_table() -> [[u8; LMR_TABLE_MOVES]; MAX_SEARCH_DEPTH as usize] {
+    let mut table = [[0; LMR_TABLE_MOVES]; MAX_SEARCH_DEPTH as usize];
+
+    for depth in 1 .. MAX_SEARCH_DEPTH as usize {
+        for move_number in 1 .. LMR_TABLE_MOVES {
+            let r = 0.75 + (depth as f64).ln() * (move_number as f64).ln() / 2.25;
+            table[depth][move_number] = min(r as usize, depth.saturating_sub(2)) as u8;
+        }
+    }
+
+    return table;
 }
 
 pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score, mut beta: Score) -> (Score, Move) {
 
-    if depth_left == 0 || context.tree.focus().outcome.is_some() {
+    let ply = context.tree.search_depth();
+
+    context.nodes += 1;
+    context.seldepth = max(context.seldepth, ply);
+
+    if context.tree.focus().outcome.is_some() {
+        return (Score::recompute_symmetric(context.tree.focus(), ply), Move::null());
+    }
+
+    if depth_left == 0 {
         //OPTIMIZE: this copy is not necessary
         context.qtree.reset_root(*context.tree.focus(), vec![]);
-        let (qscore, _) = quiescence(&mut context.qtree, alpha, beta);
+        let (qscore, _) = quiescence(context, alpha, beta);
         return (qscore, Move::null());
     }
 
-    // null move reduction
-    // TODO: add more conditions here: example, last two moves not null moves, not in end game, etc
-    // if !context.tree.focus().in_check() && context.tree.focus().board.occupied().population() > 10 {
-    //     let R = if depth_left > 6 { 3 } else { 2 };
-
-    //     let game_copy = *context.tree.focus();
-    //     context.tree.make_null_move();
-
-    //     let null_move_depth = if depth_left >= R + 1 {
-    //         depth_left - R - 1
-    //     } else {
-    //         0
-    //     };
-
-    //     let (s1,mb) = negamax(context, null_move_depth, beta.flipped(), alpha.flipped());
-    //     let s2 = s1.flipped();
-    //     context.tree.unmake_null_move(game_copy);
-
-    //     if (s2 >= beta) {
-    //         if depth_left > 2 {
-    //             depth_left -= 2; // reduce search
-    //         } else {
-    //             //OPTIMIZE: this copy is not necessary
-    //             context.qtree.reset_root(*context.tree.focus(), vec![]);
-    //             let (qscore, _) = quiescence(&mut context.qtree, alpha, beta);
-    //             return (qscore, Move::null());
-    //         }
-    //     }
-    // }
+    let is_root = ply == 0;
 
     let alpha_orig = alpha;
 
+    // only moves inside a full window can become part of the principal variation
+    let is_pv_node = beta > alpha.offset(1);
+
     let mut best_move_candidate = None;
 
     match context.table.probe(context.tree.focus().hash) {
         None => {},
         Some(tentry) => {
             best_move_candidate = Some(tentry.best_move());
-            if tentry.depth() >= depth_left {
-                let lookup_score = tentry.score();
+            // the root is always searched, so that searchmoves are respected
+            if tentry.depth() >= depth_left && !is_root {
+                let lookup_score = tentry.score().from_table(ply);
                 match tentry.node_type() {
                     NodeType::PV => return (lookup_score, Move::null()),
-                    NodeType::All => if lookup_score > alpha { alpha = lookup_score }
-                    NodeType::Cut => if lookup_score < beta { beta = lookup_score }
+                    // every move failed low there, so the score is an upper bound
+                    NodeType::All => if lookup_score < beta { beta = lookup_score }
+                    // a move failed high there, so the score is a lower bound
+                    NodeType::Cut => if lookup_score > alpha { alpha = lookup_score }
                 }
 
                 if alpha >= beta {
@@ -76,16 +317,115 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
         }
     }
 
+    // Null move pruning: if passing still fails high, a real move almost
+    // certainly would too. That breaks down in zugzwang, which is likely with
+    // only king and pawns, so those positions are skipped, and deep cutoffs
+    // are checked with a reduced search of the real moves. PV nodes are
+    // always searched properly.
+    let in_check = context.tree.focus().in_check();
+
+    let try_null_move = !is_root
+        && !is_pv_node
+        && context.null_moves_allowed
+        && depth_left >= NULL_MOVE_MIN_DEPTH
+        && !in_check
+        && !beta.is_mate()
+        && !context.tree.last_move_is_null()
+        && context.tree.focus().board.has_non_pawn_material(context.tree.focus().to_move)
+        && Score::recompute_symmetric(context.tree.focus(), ply) >= beta;
+
+    if try_null_move {
+        let reduction = 2 + depth_left / 6;
+
+        let game_copy = *context.tree.focus();
+        context.tree.make_null_move();
+        let null_score = negamax(context, depth_left.saturating_sub(reduction + 1), beta.flipped(), beta.offset(-1).flipped()).0.flipped();
+        context.tree.unmake_null_move(game_copy);
+
+        if context.aborted() {
+            return (null_score, Move::null());
+        }
+
+        if null_score >= beta {
+            // a mate found after passing can't be trusted
+            let cutoff = if null_score.is_mate() { beta } else { null_score };
+
+            if depth_left < NULL_MOVE_VERIFICATION_DEPTH {
+                return (cutoff, Move::null());
+            }
+
+            context.null_moves_allowed = false;
+            let verified_score = negamax(context, depth_left - reduction, beta.offset(-1), beta).0;
+            context.null_moves_allowed = true;
+
+            if verified_score >= beta {
+                return (cutoff, Move::null());
+            }
+        }
+    }
+
     let mut best_move = Move::null();
     let mut best_value = Score::min();
-    let next_moves = context.tree.next_moves(best_move_candidate);
+    let countermove = context.countermove();
+    let next_moves = context.tree.next_moves_ordered(best_move_candidate, |m| context.quiet_move_score(ply, countermove, m));
+    let mut root_move_number = 0;
+    let mut moves_searched = 0;
 
     for m in next_moves.borrow().iter() {
+        if is_root {
+            if !context.root_move_allowed(*m) {
+                continue;
+            }
+
+            root_move_number += 1;
+
+            if context.report_currmove && context.timer.elapsed_ms() >= CURRMOVE_DELAY_MS {
+                send!("info depth {} currmove {} currmovenumber {}", depth_left, m.to_uci_str(), root_move_number);
+            }
+        }
+
         let game_copy = *context.tree.focus();
 
         context.tree.make_move(*m);
-        let (s1,mb) = negamax(context, depth_left - 1, beta.flipped(), alpha.flipped());
-        let s2 = s1.flipped();
+
+        // Late move reductions: with good move ordering, quiet moves near the
+        // end of the list rarely matter, so they get a shallower search first.
+        let reduction = if !is_root
+            && depth_left >= LMR_MIN_DEPTH
+            && moves_searched >= LMR_MIN_MOVES
+            && !in_check
+            && !m.is_capture()
+            && !m.is_promotion()
+            && !context.is_killer(ply, *m)
+            && !context.tree.focus().in_check()
+        {
+            context.reductions[depth_left as usize][min(moves_searched, LMR_TABLE_MOVES - 1)]
+        } else {
+            0
+        };
+
+        // Principal variation search: the first move gets the full window, the
+        // rest only have to be shown no better than it with a null window, and
+        // are searched again in full if that turns out wrong.
+        let s2 = if moves_searched == 0 {
+            negamax(context, depth_left - 1, beta.flipped(), alpha.flipped()).0.flipped()
+        } else {
+            let mut null_window_score = negamax(context, depth_left - 1 - reduction, alpha.offset(1).flipped(), alpha.flipped()).0.flipped();
+
+            // a reduced move that beats alpha is looked at again at full depth
+            if reduction > 0 && null_window_score > alpha {
+                null_window_score = negamax(context, depth_left - 1, alpha.offset(1).flipped(), alpha.flipped()).0.flipped();
+            }
+
+            if null_window_score > alpha && null_window_score < beta {
+                negamax(context, depth_left - 1, beta.flipped(), alpha.flipped()).0.flipped()
+            } else {
+                null_window_score
+            }
+        };
+
+        moves_searched += 1;
+
         //TODO: make sure an additional copy is not occuring here (just a move)
         context.tree.unmake_move(game_copy);
 
@@ -99,11 +439,15 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
         }
 
         if alpha >= beta {
+            if !m.is_capture() && !m.is_promotion() {
+                context.update_quiet_move_stats(ply, *m, depth_left);
+            }
             break;
         }
 
-        if context.timer.finished() {
-            context.ran_out_of_time = true;
+        context.check_limits();
+
+        if context.aborted() {
             return (best_value, best_move);
         }
     }
@@ -118,7 +462,7 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
 
     let new_tentry = EntryData::new(
             best_move,
-            best_value,
+            best_value.to_table(ply),
             depth_left,
             new_node_type,
             //TODO: test switching this to halfmove_clock
@@ -130,11 +474,114 @@ pub fn negamax(context: &mut SearchContext, mut depth_left: u8, mut alpha: Score
     return (best_value, best_move);
 }
 
+// Searches the root with a window around the previous iteration's score,
+// widening whichever side the score falls outside of until it lands inside.
+// Shallow iterations and mate scores get the full window.
+pub fn aspiration_search(context: &mut SearchContext, depth: u8, previous_score: Option<Score>) -> (Score, Move) {
+    let previous_score = match previous_score {
+        Some(s) if depth >= ASPIRATION_MIN_DEPTH && !s.is_mate() => s,
+        _ => return negamax(context, depth, Score::min(), Score::max())
+    };
+
+    let mut delta = ASPIRATION_WINDOW;
+    let mut alpha = previous_score.offset(-delta);
+    let mut beta = previous_score.offset(delta);
+
+    loop {
+        let (score, best_move) = negamax(context, depth, alpha, beta);
+
+        if context.aborted() || (alpha == Score::min() && beta == Score::max()) {
+            return (score, best_move);
+        }
+
+        delta = delta.saturating_mul(2);
+
+        if score <= alpha {
+            debug!(target: SEARCH_TARGET, "depth {} failed low at {}", depth, score.unwrap());
+            alpha = score.offset(-delta);
+        } else if score >= beta {
+            debug!(target: SEARCH_TARGET, "depth {} failed high at {}", depth, score.unwrap());
+            beta = score.offset(delta);
+        } else {
+            return (score, best_move);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: Score,
+    // the last iteration to finish, zero if none did
+    pub depth: u8
+}
+
+// Iterative deepening from the root of the tree up to the depth and mate
+// limits, stopping early when the search is aborted or `on_depth` returns
+// false. `on_depth` is called with every finished iteration. The timer,
+// limits and abort flag are left to the caller. If not even the first
+// iteration finishes, any legal move is returned.
+pub fn iterative_deepening<F>(context: &mut SearchContext, mut on_depth: F) -> SearchResult
+    where F: FnMut(&mut SearchContext, u8, Score, Move) -> bool
+{
+    context.nodes = 0;
+    context.seldepth = 0;
+
+    let mut result = SearchResult {
+        best_move: Move::null(),
+        score: Score::new(0),
+        depth: 0
+    };
+
+    for depth in 1 .. context.limits.max_depth() + 1 {
+        context.excluded_root_moves.clear();
+
+        let previous_score = if result.depth > 0 { Some(result.score) } else { None };
+        let (score, best_move) = aspiration_search(context, depth, previous_score);
+
+        // an interrupted iteration hasn't looked at every move
+        if context.aborted() || best_move.is_null() {
+            break;
+        }
+
+        result = SearchResult {
+            best_move: best_move,
+            score: score,
+            depth: depth
+        };
+
+        if !on_depth(context, depth, score, best_move) || context.aborted() {
+            break;
+        }
+
+        if let (Some(moves), Some(mate_in)) = (context.limits.mate, score.mate_in()) {
+            if mate_in > 0 && mate_in as u32 <= moves {
+                break;
+            }
+        }
+    }
+
+    if result.best_move.is_null() {
+        let legal_moves = next_moves_standalone(context.tree.focus());
+        if legal_moves.len() > 0 {
+            result.best_move = legal_moves.at(0);
+        }
+    }
+
+    result
+}
+
 //TODO: don't bother returning a Move from this function
-pub fn quiescence(tree: &mut SearchTree, mut alpha: Score, mut beta: Score) -> (Score, Move) {
-    debug_assert!(tree.in_quiescence);
+pub fn quiescence(context: &mut SearchContext, mut alpha: Score, mut beta: Score) -> (Score, Move) {
+    debug_assert!(context.qtree.in_quiescence);
 
-    let stand_pat = Score::recompute_symmetric(&tree.focus(), tree.search_depth());
+    context.nodes += 1;
+
+    // the quiescence tree is rooted at a leaf of the main tree
+    let ply = context.tree.search_depth() + context.qtree.search_depth();
+    context.seldepth = max(context.seldepth, ply);
+
+    let stand_pat = Score::recompute_symmetric(&context.qtree.focus(), ply);
 
     if stand_pat >= beta {
         return (beta, Move::null());
@@ -144,14 +591,14 @@ pub fn quiescence(tree: &mut SearchTree, mut alpha: Score, mut beta: Score) -> (
         alpha = stand_pat;
     }
 
-    let next_moves = tree.next_moves(None);
+    let next_moves = context.qtree.next_moves(None);
 
     for m in next_moves.borrow().iter() {
-        let game_copy = *tree.focus();
+        let game_copy = *context.qtree.focus();
 
-        tree.make_move(*m);
-        let (s1,_) = quiescence(tree, beta.flipped(), alpha.flipped());
-        tree.unmake_move(game_copy);
+        context.qtree.make_move(*m);
+        let (s1,_) = quiescence(context, beta.flipped(), alpha.flipped());
+        context.qtree.unmake_move(game_copy);
         let s2 = s1.flipped();
 
         if s2 >= beta {
@@ -165,3 +612,140 @@ pub fn quiescence(tree: &mut SearchTree, mut alpha: Score, mut beta: Score) -> (
 
     return (alpha, Move::null());
 }
+
+#[cfg(test)]
+mod test {
+    use search::*;
+
+    fn test_context(fen: &str) -> SearchContext {
+        init_zobrist_hashing();
+        let game = Game::from_fen_str(fen).unwrap();
+        SearchContext::new(game, TranspositionTable::with_size_mb(1), Arc::new(AtomicBool::new(false)))
+    }
+
+    // iterative deepening with the full window at every depth
+    fn full_window_search(context: &mut SearchContext, depth: u8) -> (Score, Move) {
+        let mut result = (Score::new(0), Move::null());
+        for d in 1 .. depth + 1 {
+            result = negamax(context, d, Score::min(), Score::max());
+        }
+        result
+    }
+
+    const TEST_POSITIONS: [&'static str; 6] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 10",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+        "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+        "r1bq1r1k/1pp1n1pp/1p1p4/4p2Q/4Pp2/1BNP4/PPP2PPP/3R1RK1 w - - 2 14",
+        "6k1/6p1/6Pp/ppp5/3pn2P/1P3K2/1PP2P2/8 b - - 3 54"
+    ];
+
+    #[test]
+    fn aspiration_matches_full_window() {
+        for fen in TEST_POSITIONS.iter() {
+            let mut full = test_context(fen);
+            let (full_score, _) = full_window_search(&mut full, 5);
+
+            let mut aspiration = test_context(fen);
+            aspiration.limits.depth = Some(5);
+            let result = iterative_deepening(&mut aspiration, |_, _, _, _| true);
+
+            assert_eq!(result.score, full_score, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn aspiration_widens_window() {
+        let fen = TEST_POSITIONS[1];
+
+        let mut full = test_context(fen);
+        let (full_score, _) = full_window_search(&mut full, 5);
+
+        // a guess far above the real score fails low, one far below fails high
+        for &guess in [600, -600].iter() {
+            let mut context = test_context(fen);
+            full_window_search(&mut context, 4);
+            let (score, best_move) = aspiration_search(&mut context, 5, Some(Score::new(guess)));
+
+            assert_eq!(score, full_score);
+            assert!(!best_move.is_null());
+        }
+    }
+
+    fn best_move_at_depth(context: &mut SearchContext, depth: u8) -> String {
+        context.limits.depth = Some(depth);
+        iterative_deepening(context, |_, _, _, _| true).best_move.to_uci_str()
+    }
+
+    #[test]
+    fn null_move_skipped_in_pawn_endings() {
+        // black's king can't catch the f-pawn, unless white is allowed to pass
+        let fen = "8/8/k7/8/PP3P2/8/8/6K1 w - - 0 1";
+        assert_eq!(best_move_at_depth(&mut test_context(fen), 8), "f4f5");
+
+        let mut without_null_moves = test_context(fen);
+        without_null_moves.null_moves_allowed = false;
+        assert_eq!(best_move_at_depth(&mut without_null_moves, 8), "f4f5");
+    }
+
+    #[test]
+    fn null_move_verification() {
+        // every king move but Kh6 lets black off the hook, which null moves
+        // at the deeper nodes only see after the verification search
+        let fen = "1q1k4/2Rr4/8/2Q3K1/8/8/8/8 w - - 0 1";
+        assert_eq!(best_move_at_depth(&mut test_context(fen), 8), "g5h6");
+    }
+
+    #[test]
+    fn reductions_are_searched_again() {
+        // a reduced move that beats alpha is searched again at full depth, so
+        // the result matches a search without any reductions
+        for fen in ["r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "r3k2r/8/8/8/8/8/8/4K3 w kq - 0 1"].iter() {
+            let mut reduced = test_context(fen);
+            reduced.limits.depth = Some(7);
+            let reduced_result = iterative_deepening(&mut reduced, |_, _, _, _| true);
+
+            let mut unreduced = test_context(fen);
+            unreduced.reductions = [[0; LMR_TABLE_MOVES]; MAX_SEARCH_DEPTH as usize];
+            unreduced.limits.depth = Some(7);
+            let unreduced_result = iterative_deepening(&mut unreduced, |_, _, _, _| true);
+
+            assert_eq!(reduced_result.best_move, unreduced_result.best_move, "{}", fen);
+            assert_eq!(reduced_result.score, unreduced_result.score, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn quiet_move_ordering() {
+        init_zobrist_hashing();
+
+        let game = Game::starting_position();
+        let abort = Arc::new(AtomicBool::new(false));
+        let mut context = SearchContext::new(game, TranspositionTable::with_size_mb(1), abort);
+
+        let e2e4 = move_from_algebraic(&game, "e2e4".to_string()).unwrap();
+        let d2d4 = move_from_algebraic(&game, "d2d4".to_string()).unwrap();
+        let g1f3 = move_from_algebraic(&game, "g1f3".to_string()).unwrap();
+
+        context.update_quiet_move_stats(0, e2e4, 3);
+        context.update_quiet_move_stats(0, d2d4, 2);
+        context.update_quiet_move_stats(0, d2d4, 2);
+        assert_eq!(context.killers[0], [d2d4, e2e4]);
+        assert_eq!(context.history[Color::White as usize][e2e4.from().idx()][e2e4.to().idx()], 9);
+
+        let next_moves = context.tree.next_moves_ordered(None, |m| context.quiet_move_score(0, g1f3, m));
+        let ordered: Vec<Move> = next_moves.borrow().iter().take(3).cloned().collect();
+        assert_eq!(ordered, vec![d2d4, e2e4, g1f3]);
+
+        // the countermove is remembered for the move that was refuted
+        context.tree.make_move(e2e4);
+        let e7e5 = move_from_algebraic(context.tree.focus(), "e7e5".to_string()).unwrap();
+        context.update_quiet_move_stats(1, e7e5, 4);
+        assert_eq!(context.countermove(), e7e5);
+
+        context.clear_move_ordering();
+        assert_eq!(context.countermove(), Move::null());
+        assert!(!context.is_killer(0, d2d4));
+    }
+}
@@ -0,0 +1,159 @@
+use core::*;
+use epd::*;
+use eval::*;
+use game::*;
+use movegen::*;
+use moves::*;
+use san::*;
+use search::*;
+use zobrist::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const DEFAULT_TEST_MOVETIME_MS: u32 = 1000;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TestResult {
+    pub id: String,
+    pub passed: bool,
+    pub best_move: Move,
+    pub depth: u8,
+    // when the search settled on a correct move for good
+    pub solution_time_ms: Option<u64>
+}
+
+// a move solves a position if it is one of the best moves and none of the ones to avoid
+fn is_solution(entry: &EpdEntry, m: Move) -> bool {
+    let best_moves = entry.best_moves();
+    (best_moves.is_empty() || best_moves.contains(&m)) && !entry.avoid_moves().contains(&m)
+}
+
+fn run_position(context: &mut SearchContext, entry: &EpdEntry, id: String, movetime_ms: u32) -> TestResult {
+    let game = entry.game;
+
+    context.new_game();
+    context.tree.reset_root(game, Vec::new());
+    context.abort.store(false, Ordering::SeqCst);
+    context.timer = SearchTimer::new(movetime_ms);
+    context.limits = SearchLimits::new();
+
+    let mut solved_since = None;
+
+    let result = iterative_deepening(context, |context, _, score, m| {
+        if !is_solution(entry, m) {
+            solved_since = None;
+        } else if solved_since.is_none() {
+            solved_since = Some(context.timer.elapsed_ms());
+        }
+
+        score.mate_in().is_none()
+    });
+
+    TestResult {
+        id: id,
+        passed: !result.best_move.is_null() && is_solution(entry, result.best_move),
+        best_move: result.best_move,
+        depth: result.depth,
+        solution_time_ms: solved_since
+    }
+}
+
+// Searches each position for the given time and checks the move found
+// against its `bm` and `am` operations. Positions with neither are skipped.
+pub fn run_test_suite(entries: &[EpdEntry], movetime_ms: u32, hash_mb: usize, verbose: bool) -> Vec<TestResult> {
+    init_zobrist_hashing();
+
+    let abort = Arc::new(AtomicBool::new(false));
+    let mut context = SearchContext::new(Game::starting_position(), TranspositionTable::with_size_mb(hash_mb), abort);
+
+    let testable: Vec<&EpdEntry> = entries.iter()
+        .filter(|e| !e.best_moves().is_empty() || !e.avoid_moves().is_empty())
+        .collect();
+
+    let mut results = Vec::new();
+
+    for (idx, entry) in testable.iter().enumerate() {
+        let id = entry.id().map(|s| s.to_string()).unwrap_or(format!("#{}", idx + 1));
+        let result = run_position(&mut context, entry, id, movetime_ms);
+
+        if verbose {
+            let found = move_to_san(&entry.game, result.best_move);
+
+            let to_san_list = |moves: Vec<Move>| -> String {
+                moves.iter().map(|m| move_to_san(&entry.game, *m)).collect::<Vec<String>>().join(" ")
+            };
+
+            let expected = if !entry.best_moves().is_empty() {
+                format!("bm {}", to_san_list(entry.best_moves()))
+            } else {
+                format!("am {}", to_san_list(entry.avoid_moves()))
+            };
+
+            let outcome = match result.solution_time_ms {
+                Some(ms) if result.passed => format!("pass  {:>7.2} s", ms as f64 / 1000.0),
+                _ => "FAIL           ".to_string()
+            };
+
+            println!("{:>4}/{}  {:<16} {}  found {:<8} {}  depth {}",
+                     idx + 1, testable.len(), result.id, outcome, found, expected, result.depth);
+        }
+
+        results.push(result);
+    }
+
+    if verbose && testable.len() < entries.len() {
+        println!("skipped {} positions without bm or am", entries.len() - testable.len());
+    }
+
+    return results;
+}
+
+pub fn test_suite(path: &str, movetime_ms: u32, hash_mb: usize) -> Result<(), String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("couldn't read {}: {}", path, e))?;
+
+    let entries = parse_epd_file(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let results = run_test_suite(&entries, movetime_ms, hash_mb, true);
+
+    let passed: Vec<&TestResult> = results.iter().filter(|r| r.passed).collect();
+    let total_solution_ms: u64 = passed.iter().filter_map(|r| r.solution_time_ms).sum();
+
+    println!("");
+    println!("Score: {}/{} ({:.1}%)", passed.len(), results.len(),
+             100.0 * passed.len() as f64 / results.len().max(1) as f64);
+    println!("Total time to solution: {:.2} s", total_solution_ms as f64 / 1000.0);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use testsuite::*;
+
+    #[test]
+    fn scoring() {
+        let suite = "\
+# mate in one, a move to avoid, and a wrong best move
+6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - bm Ra8#; id \"back rank\";
+4k3/8/8/8/8/8/3q4/4K3 w - - am Kf1; id \"avoid\";
+6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - bm h3; id \"wrong\";
+4k3/8/8/8/8/8/8/4K3 w - - id \"untested\";
+";
+
+        let entries = parse_epd_file(suite).unwrap();
+        let results = run_test_suite(&entries, 200, 1, false);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results.iter().map(|r| r.id.as_str()).collect::<Vec<&str>>(), vec!["back rank", "avoid", "wrong"]);
+        assert_eq!(results.iter().map(|r| r.passed).collect::<Vec<bool>>(), vec![true, true, false]);
+
+        assert!(results[0].solution_time_ms.is_some());
+        assert_eq!(results[1].best_move.to_uci_str(), "e1d2");
+        assert_eq!(results[2].solution_time_ms, None);
+    }
+}
@@ -32,6 +32,10 @@ impl SearchTree {
         *self.current_line.last().unwrap()
     }
 
+    pub fn last_move_is_null(&self) -> bool {
+        self.current_line.last().map_or(false, |m| m.is_null())
+    }
+
     pub fn search_depth(&self) -> usize {
         self.search_depth
     }
@@ -58,6 +62,12 @@ impl SearchTree {
     }
 
     pub fn next_moves(&self, best_move_candidate: Option<Move>) -> MoveBuffer {
+        self.next_moves_ordered(best_move_candidate, |_| 0)
+    }
+
+    pub fn next_moves_ordered<F>(&self, best_move_candidate: Option<Move>, quiet_score: F) -> MoveBuffer
+        where F: Fn(Move) -> i32
+    {
         {
             let buf = self.move_stack[self.search_depth].clone();
             if self.in_quiescence {
@@ -66,7 +76,7 @@ impl SearchTree {
             } else {
                 generate_moves(&self.game, buf.clone(), false);
             }
-            buf.borrow_mut().sort(best_move_candidate);
+            buf.borrow_mut().sort_with_quiet_scores(best_move_candidate, quiet_score);
         }
 
         self.move_stack[self.search_depth].clone()
@@ -74,6 +84,7 @@ impl SearchTree {
 
     pub fn make_null_move(&mut self) {
         self.game.make_null_move();
+        self.current_line.push(Move::null());
         self.search_depth += 1;
         self.move_stack[self.search_depth].borrow_mut().clear();
     }
@@ -107,6 +118,7 @@ impl SearchTree {
         self.move_stack[self.search_depth].borrow_mut().clear();
         self.search_depth -= 1;
         self.game = previous_game;
+        self.current_line.pop();
     }
 
     // currently we unmake move by copy
@@ -1,123 +1,520 @@
 use std::io::stdin;
 use std::io::BufRead;
+use std::fmt;
 use std::str::SplitWhitespace;
 
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-
 use game::*;
+use logging::*;
 use movegen::*;
 use moves::*;
+use search::*;
 use zobrist::*;
 
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UCICommand {
+    Uci,
+    Debug(bool),
+    IsReady,
+    SetOption { name: String, value: String },
+    Register,
+    UciNewGame,
+    Position { game: Game, history: Vec<Hash> },
+    Go(SearchLimits),
+    Stop,
+    PonderHit,
+    Quit
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UCIError {
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidArgument(String),
+    InvalidNumber(String),
+    InvalidFen(String),
+    IllegalMove(String),
+    UnknownOption(String),
+    InvalidOptionValue(String)
+}
+
+impl fmt::Display for UCIError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UCIError::UnknownCommand(ref cmd) => write!(f, "unknown command: {}", cmd),
+            UCIError::MissingArgument(what) => write!(f, "missing {}", what),
+            UCIError::InvalidArgument(ref arg) => write!(f, "invalid argument: {}", arg),
+            UCIError::InvalidNumber(ref num) => write!(f, "invalid number: {}", num),
+            UCIError::InvalidFen(ref fen) => write!(f, "invalid FEN: {}", fen),
+            UCIError::IllegalMove(ref m) => write!(f, "illegal move: {}", m),
+            UCIError::UnknownOption(ref name) => write!(f, "unknown option: {}", name),
+            UCIError::InvalidOptionValue(ref msg) => write!(f, "{}", msg)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UCIOptionType {
+    Check { default: bool },
+    Spin { default: i64, min: i64, max: i64 },
+    Combo { default: &'static str, vars: Vec<&'static str> },
+    Button,
+    String { default: String }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UCIOptionValue {
+    Check(bool),
+    Spin(i64),
+    Combo(String),
+    Button,
+    String(String)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UCIOption {
+    pub name: &'static str,
+    pub option_type: UCIOptionType
+}
+
+impl UCIOption {
+    pub fn check(name: &'static str, default: bool) -> UCIOption {
+        UCIOption { name: name, option_type: UCIOptionType::Check { default } }
+    }
+
+    pub fn spin(name: &'static str, default: i64, min: i64, max: i64) -> UCIOption {
+        UCIOption { name: name, option_type: UCIOptionType::Spin { default, min, max } }
+    }
+
+    pub fn combo(name: &'static str, default: &'static str, vars: Vec<&'static str>) -> UCIOption {
+        UCIOption { name: name, option_type: UCIOptionType::Combo { default, vars } }
+    }
+
+    pub fn button(name: &'static str) -> UCIOption {
+        UCIOption { name: name, option_type: UCIOptionType::Button }
+    }
+
+    pub fn string(name: &'static str, default: &str) -> UCIOption {
+        UCIOption { name: name, option_type: UCIOptionType::String { default: default.to_string() } }
+    }
+
+    // the line advertising this option in response to `uci`
+    pub fn to_uci_str(&self) -> String {
+        match self.option_type {
+            UCIOptionType::Check { default } =>
+                format!("option name {} type check default {}", self.name, default),
+            UCIOptionType::Spin { default, min, max } =>
+                format!("option name {} type spin default {} min {} max {}", self.name, default, min, max),
+            UCIOptionType::Combo { default, ref vars } => {
+                let mut s = format!("option name {} type combo default {}", self.name, default);
+                for var in vars.iter() {
+                    s.push_str(" var ");
+                    s.push_str(var);
+                }
+                s
+            },
+            UCIOptionType::Button =>
+                format!("option name {} type button", self.name),
+            UCIOptionType::String { ref default } => {
+                let default = if default.is_empty() { "<empty>" } else { default };
+                format!("option name {} type string default {}", self.name, default)
+            }
+        }
+    }
+
+    pub fn parse_value(&self, value: &str) -> Result<UCIOptionValue, UCIError> {
+        match self.option_type {
+            UCIOptionType::Check { .. } => match value {
+                "true" => Ok(UCIOptionValue::Check(true)),
+                "false" => Ok(UCIOptionValue::Check(false)),
+                _ => Err(UCIError::InvalidOptionValue(format!("{} expects true or false, got '{}'", self.name, value)))
+            },
+
+            UCIOptionType::Spin { min, max, .. } => match value.parse::<i64>() {
+                Ok(x) if x >= min && x <= max => Ok(UCIOptionValue::Spin(x)),
+                Ok(x) => Err(UCIError::InvalidOptionValue(format!("{} must be between {} and {}, got {}", self.name, min, max, x))),
+                Err(_) => Err(UCIError::InvalidOptionValue(format!("{} expects an integer, got '{}'", self.name, value)))
+            },
+
+            UCIOptionType::Combo { ref vars, .. } => {
+                match vars.iter().find(|v| v.eq_ignore_ascii_case(value)) {
+                    Some(v) => Ok(UCIOptionValue::Combo(v.to_string())),
+                    None => Err(UCIError::InvalidOptionValue(format!("'{}' is not a valid choice for {}", value, self.name)))
+                }
+            },
+
+            UCIOptionType::Button => Ok(UCIOptionValue::Button),
+
+            UCIOptionType::String { .. } => {
+                if value == "<empty>" {
+                    Ok(UCIOptionValue::String(String::new()))
+                } else {
+                    Ok(UCIOptionValue::String(value.to_string()))
+                }
+            }
+        }
+    }
+}
+
 pub trait UCIEngine {
     fn name(&self) -> &'static str;
     fn author(&self) -> &'static str;
     fn init(&mut self) -> () {}
     fn reset(&mut self) -> () {}
     fn replace_game(&mut self, new_game: Game, history: Vec<Hash>);
-    fn find_best_move(&mut self, wtime: u32, btime: u32, winc: u32, binc: u32) -> ();
+    fn current_game(&self) -> Game;
+    fn find_best_move(&mut self, limits: SearchLimits) -> ();
     // fn infinite_search(&mut self) -> ();
 
-    //TODO: move to UCIEngine trait default implementation
-    fn update_position<'a>(&mut self, args: &mut SplitWhitespace<'a>) {
-        let mut g = Game::empty_position();
+    // engines that search on a separate thread must return from find_best_move
+    // immediately, and print `bestmove` once stop/ponder_hit/quit end the search
+    fn stop(&mut self) -> () {}
+    fn ponder_hit(&mut self) -> () {}
+    fn quit(&mut self) -> () {}
+    fn set_debug(&mut self, _on: bool) -> () {}
+
+    // should block until earlier commands (e.g. a hash resize) have been applied
+    fn wait_until_ready(&mut self) -> () {}
 
-        match args.next() {
-            Some("startpos") => g = Game::starting_position(),
-            Some("fen") => {
-                g = Game::from_fen(args).unwrap();
+    fn options(&self) -> Vec<UCIOption> { Vec::new() }
+    fn set_option(&mut self, _name: &'static str, _value: UCIOptionValue) -> () {}
+
+    fn apply_option(&mut self, name: &str, value: &str) -> Result<(), UCIError> {
+        // option names are case insensitive
+        match self.options().into_iter().find(|opt| opt.name.eq_ignore_ascii_case(name)) {
+            None => Err(UCIError::UnknownOption(name.to_string())),
+            Some(opt) => {
+                let v = opt.parse_value(value)?;
+                self.set_option(opt.name, v);
+                Ok(())
             }
-            _ => {
-                eprintln!("error! invalid position string passed!");
-                return;
+        }
+    }
+
+    // returns false once the engine should shut down
+    fn execute(&mut self, cmd: UCICommand) -> Result<bool, UCIError> {
+        match cmd {
+            UCICommand::Uci => {
+                send!("id name {}", self.name());
+                send!("id author {}", self.author());
+                for opt in self.options().iter() {
+                    send!("{}", opt.to_uci_str());
+                }
+                send!("uciok");
+            },
+
+            UCICommand::Debug(on)                  => self.set_debug(on),
+            UCICommand::IsReady                    => {
+                self.wait_until_ready();
+                send!("readyok");
+            },
+            UCICommand::SetOption { name, value }  => self.apply_option(&name, &value)?,
+            UCICommand::Register                   => {},
+            UCICommand::UciNewGame                 => self.reset(),
+            UCICommand::Position { game, history } => self.replace_game(game, history),
+            UCICommand::Go(limits)                 => self.find_best_move(limits),
+            UCICommand::Stop                       => self.stop(),
+            UCICommand::PonderHit                  => self.ponder_hit(),
+            UCICommand::Quit                       => return Ok(false)
+        }
+
+        Ok(true)
+    }
+
+    fn run(&mut self) -> () {
+        let stdin = stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => {
+                    error!("failed to read from gui/server: {}", e);
+                    break;
+                }
+            };
+
+            info!(target: INPUT_TARGET, "{}", line);
+
+            let result = parse_uci_command(&line, &self.current_game())
+                .and_then(|cmd| match cmd {
+                    Some(cmd) => self.execute(cmd),
+                    None => Ok(true)
+                });
+
+            match result {
+                Ok(true) => {},
+                Ok(false) => break,
+                Err(e) => {
+                    warn!("{}: {}", e, line);
+                    send!("info string {}", e);
+                }
             }
         }
 
-        match args.next() {
-            Some("moves") => {},
+        self.quit();
+    }
+}
+
+// Returns Ok(None) for blank lines. As the protocol asks, unknown leading
+// tokens are skipped, so `joho debug on` still turns debug mode on.
+pub fn parse_uci_command(line: &str, game: &Game) -> Result<Option<UCICommand>, UCIError> {
+    let mut args = line.split_whitespace();
+    let mut unknown = None;
+
+    while let Some(word) = args.next() {
+        let cmd = match word {
+            "uci"        => UCICommand::Uci,
+            "debug"      => match args.next() {
+                Some("on") => UCICommand::Debug(true),
+                Some("off") => UCICommand::Debug(false),
+                Some(x) => return Err(UCIError::InvalidArgument(format!("debug {}", x))),
+                None => return Err(UCIError::MissingArgument("on or off after debug"))
+            },
+            "isready"    => UCICommand::IsReady,
+            "setoption"  => parse_setoption(&mut args)?,
+            "register"   => UCICommand::Register,
+            "ucinewgame" => UCICommand::UciNewGame,
+            "position"   => parse_position(&mut args)?,
+            "go"         => UCICommand::Go(parse_go(&mut args, game)?),
+            "stop"       => UCICommand::Stop,
+            "ponderhit"  => UCICommand::PonderHit,
+            "quit"       => UCICommand::Quit,
             _ => {
-                self.replace_game(g, Vec::new());
-                return
+                if unknown.is_none() {
+                    unknown = Some(word.to_string());
+                }
+                continue;
             }
+        };
+
+        return Ok(Some(cmd));
+    }
+
+    match unknown {
+        Some(word) => Err(UCIError::UnknownCommand(word)),
+        None => Ok(None)
+    }
+}
+
+fn parse_setoption<'a>(args: &mut SplitWhitespace<'a>) -> Result<UCICommand, UCIError> {
+    let mut name_words = Vec::new();
+    let mut value_words = Vec::new();
+    let mut reading_value = false;
+
+    match args.next() {
+        Some("name") => {},
+        _ => return Err(UCIError::MissingArgument("name after setoption"))
+    }
+
+    for word in args {
+        if word == "value" && !reading_value {
+            reading_value = true;
+        } else if reading_value {
+            value_words.push(word);
+        } else {
+            name_words.push(word);
         }
+    }
+
+    if name_words.is_empty() {
+        return Err(UCIError::MissingArgument("option name"));
+    }
+
+    Ok(UCICommand::SetOption {
+        name: name_words.join(" "),
+        value: value_words.join(" ")
+    })
+}
+
+fn parse_position<'a>(args: &mut SplitWhitespace<'a>) -> Result<UCICommand, UCIError> {
+    let mut g = match args.next() {
+        Some("startpos") => {
+            match args.next() {
+                Some("moves") | None => {},
+                Some(x) => return Err(UCIError::InvalidArgument(x.to_string()))
+            }
+            Game::starting_position()
+        },
+        Some("fen") => {
+            let fen_words: Vec<&str> = args.by_ref().take_while(|w| *w != "moves").collect();
+            let fen = fen_words.join(" ");
+            match Game::from_fen_str(&fen) {
+                Some(g) => g,
+                None => return Err(UCIError::InvalidFen(fen))
+            }
+        },
+        Some(x) => return Err(UCIError::InvalidArgument(x.to_string())),
+        None => return Err(UCIError::MissingArgument("startpos or fen after position"))
+    };
+
+    let mut history = Vec::new();
 
-        let mut history = Vec::new();
-        loop {
-            if let Some(move_str) = args.next() {
-                let m = move_from_algebraic(&g, move_str.to_string()).unwrap();
+    for move_str in args {
+        match move_from_algebraic(&g, move_str.to_string()) {
+            Some(m) => {
                 g.make_move(m);
                 history.push(g.hash);
-            } else {
-                break;
-            }
+            },
+            None => return Err(UCIError::IllegalMove(move_str.to_string()))
         }
+    }
 
-        eprintln!("FEN re-created by feldspar: {}", g.to_fen());
+    debug!("FEN re-created by feldspar: {}", g.to_fen());
 
-        self.replace_game(g, history);
-    }
+    Ok(UCICommand::Position { game: g, history: history })
+}
 
-    fn parse_go_cmd<'a>(&mut self, args: &mut SplitWhitespace<'a>) {
+fn parse_go<'a>(args: &mut SplitWhitespace<'a>, game: &Game) -> Result<SearchLimits, UCIError> {
+    let mut limits = SearchLimits::new();
 
-        let mut wtime = 0;
-        let mut btime = 0;
-        let mut winc = 0;
-        let mut binc = 0;
+    // searchmoves takes every following token up to the next keyword
+    let mut reading_searchmoves = false;
 
-        loop {
-            match args.next() {
-                Some("wtime") => wtime = args.next().unwrap().parse().unwrap(),
-                Some("btime") => btime = args.next().unwrap().parse().unwrap(),
-                Some("winc") => winc = args.next().unwrap().parse().unwrap(),
-                Some("binc") => binc = args.next().unwrap().parse().unwrap(),
-                Some(_) => break,
-                None => break
+    while let Some(token) = args.next() {
+        match token {
+            "wtime"     => limits.wtime = Some(next_number(args, token)?),
+            "btime"     => limits.btime = Some(next_number(args, token)?),
+            "winc"      => limits.winc = next_number(args, token)?,
+            "binc"      => limits.binc = next_number(args, token)?,
+            "movestogo" => limits.movestogo = Some(next_number(args, token)?),
+            "depth"     => limits.depth = Some(next_number(args, token)?),
+            "nodes"     => limits.nodes = Some(next_number(args, token)?),
+            "movetime"  => limits.movetime = Some(next_number(args, token)?),
+            "mate"      => limits.mate = Some(next_number(args, token)?),
+            "infinite"  => limits.infinite = true,
+            "ponder"    => limits.ponder = true,
+            "searchmoves" => {
+                reading_searchmoves = true;
+                continue;
+            },
+            _ => {
+                if reading_searchmoves {
+                    match move_from_algebraic(game, token.to_string()) {
+                        Some(m) => {
+                            limits.searchmoves.push(m);
+                            continue;
+                        },
+                        None => return Err(UCIError::IllegalMove(token.to_string()))
+                    }
+                }
+                return Err(UCIError::InvalidArgument(format!("go {}", token)));
             }
         }
 
-        self.find_best_move(wtime, btime, winc, binc);
+        reading_searchmoves = false;
     }
 
-    fn run(&mut self) -> () {
-        let stdin = stdin();
-        for line in stdin.lock().lines() {
-            eprintln!("line before received from gui/server: {:?}", line);
-            let line = line.unwrap_or("".into());
-            eprintln!("line received from gui/server: {}", line);
-
-            let mut file = OpenOptions::new()
-                .write(true)
-                .append(true)
-                .open("C:/Code/feldspar/log.txt")
-                .unwrap();
-
-            if let Err(e) = writeln!(file, "{}", line) {
-                eprintln!("Couldn't write to file: {}", e);
-            }
+    Ok(limits)
+}
+
+fn next_number<'a, T: FromStr>(args: &mut SplitWhitespace<'a>, name: &str) -> Result<T, UCIError> {
+    match args.next() {
+        Some(x) => x.parse().map_err(|_| UCIError::InvalidNumber(format!("{} {}", name, x))),
+        None => Err(UCIError::InvalidNumber(format!("{} <missing>", name)))
+    }
+}
 
+#[cfg(test)]
+mod test {
+    use uci::*;
 
-            let mut params = line.split_whitespace();
+    fn parse_transcript(transcript: &str) -> Vec<Result<Option<UCICommand>, UCIError>> {
+        let game = Game::starting_position();
+        transcript.lines().map(|line| parse_uci_command(line, &game)).collect()
+    }
 
-            if let Some(first_word) = params.next() {
+    #[test]
+    fn well_formed() {
+        let game = Game::starting_position();
 
-                match first_word {
+        assert_eq!(parse_uci_command("uci", &game), Ok(Some(UCICommand::Uci)));
+        assert_eq!(parse_uci_command("   ", &game), Ok(None));
+        assert_eq!(parse_uci_command("joho debug on", &game), Ok(Some(UCICommand::Debug(true))));
+        assert_eq!(parse_uci_command("setoption name Clear Hash", &game),
+                   Ok(Some(UCICommand::SetOption { name: "Clear Hash".to_string(), value: String::new() })));
+        assert_eq!(parse_uci_command("setoption name Move Overhead value 100", &game),
+                   Ok(Some(UCICommand::SetOption { name: "Move Overhead".to_string(), value: "100".to_string() })));
 
-                    "uci" => {
-                        println!("id name {}", self.name());
-                        println!("id author {}", self.author());
-                        println!("uciok");
-                    },
+        match parse_uci_command("go wtime 1000 btime 2000 winc 10 binc 20 movestogo 5 depth 7 nodes 1000 movetime 50 mate 3 infinite ponder searchmoves e2e4 d2d4", &game) {
+            Ok(Some(UCICommand::Go(limits))) => {
+                assert_eq!(limits.wtime, Some(1000));
+                assert_eq!(limits.btime, Some(2000));
+                assert_eq!(limits.winc, 10);
+                assert_eq!(limits.binc, 20);
+                assert_eq!(limits.movestogo, Some(5));
+                assert_eq!(limits.depth, Some(7));
+                assert_eq!(limits.nodes, Some(1000));
+                assert_eq!(limits.movetime, Some(50));
+                assert_eq!(limits.mate, Some(3));
+                assert!(limits.infinite);
+                assert!(limits.ponder);
+                assert_eq!(limits.searchmoves.len(), 2);
+            },
+            x => panic!("unexpected parse result: {:?}", x)
+        }
 
-                    "setoption" => {},
-                    "isready"    => println!("readyok"),
-                    "ucinewgame" => self.reset(),
-                    "position"   => self.update_position(&mut params),
-                    "quit"       => return,
-                    "go"         => self.parse_go_cmd(&mut params),
-                    _ => println!("Un-used command from GUI/server: {}", first_word)
-                }
-            }
+        match parse_uci_command("position startpos moves e2e4 e7e5 g1f3", &game) {
+            Ok(Some(UCICommand::Position { game, history })) => {
+                assert_eq!(game.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+                assert_eq!(history.len(), 3);
+            },
+            x => panic!("unexpected parse result: {:?}", x)
+        }
+
+        match parse_uci_command("position fen 8/P7/8/8/8/8/k7/6K1 w - - 0 1 moves a7a8q", &game) {
+            Ok(Some(UCICommand::Position { game, .. })) => {
+                assert_eq!(game.to_fen(), "Q7/8/8/8/8/8/k7/6K1 b - - 0 1");
+            },
+            x => panic!("unexpected parse result: {:?}", x)
         }
     }
+
+    #[test]
+    fn malformed_transcript() {
+        let transcript = "\
+            foo\n\
+            debug maybe\n\
+            debug\n\
+            setoption\n\
+            setoption name\n\
+            setoption Hash value 5\n\
+            position\n\
+            position somewhere\n\
+            position startpos e2e4\n\
+            position startpos moves e2e5\n\
+            position startpos moves e2e4 e2e4\n\
+            position startpos moves zz99\n\
+            position startpos moves é2e4\n\
+            position fen\n\
+            position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR\n\
+            position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1\n\
+            position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - zero 1\n\
+            position fen rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\n\
+            position fen rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1\n\
+            position fen 8/8/8/8/8/8/8/8 w - - 0 1 moves e2e4\n\
+            go depth\n\
+            go depth ten\n\
+            go wtime -5\n\
+            go nodes 1e6\n\
+            go sideways\n\
+            go searchmoves e2e4 e7e5\n\
+            go movetime 99999999999999999999";
+
+        for (i, result) in parse_transcript(transcript).iter().enumerate() {
+            assert!(result.is_err(), "line {} should not parse: {:?}", i, result);
+        }
+    }
+
+    #[test]
+    fn option_values() {
+        let hash = UCIOption::spin("Hash", 16, 1, 1024);
+        assert_eq!(hash.parse_value("64"), Ok(UCIOptionValue::Spin(64)));
+        assert!(hash.parse_value("0").is_err());
+        assert!(hash.parse_value("lots").is_err());
+        assert!(hash.parse_value("").is_err());
+
+        let ponder = UCIOption::check("Ponder", false);
+        assert_eq!(ponder.parse_value("true"), Ok(UCIOptionValue::Check(true)));
+        assert!(ponder.parse_value("yes").is_err());
+    }
 }
@@ -0,0 +1,567 @@
+use core::*;
+use feldspar::*;
+use game::*;
+use logging::*;
+use movegen::*;
+use moves::*;
+use search::*;
+use uci::*;
+use zobrist::*;
+
+use std::io::stdin;
+use std::io::BufRead;
+use std::fmt;
+use std::str::SplitWhitespace;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum XBoardCommand {
+    XBoard,
+    Protover(u32),
+    Accepted,
+    Rejected,
+    New,
+    Force,
+    Go,
+    PlayOther,
+    UserMove(Move),
+    SetBoard(Game),
+    Level { moves_per_session: Option<u32>, base_ms: u32, increment_ms: u32 },
+    St(u32),
+    Sd(u8),
+    Time(u32),
+    Otim(u32),
+    Undo,
+    Remove,
+    Post,
+    NoPost,
+    Result(String),
+    Ping(String),
+    MoveNow,
+    Memory(u32),
+    // commands we accept but have no use for, e.g. `hard`, `random` or `computer`
+    Ignored,
+    Quit
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum XBoardError {
+    UnknownCommand(String),
+    IllegalMove(String),
+    InvalidArgument(String),
+    InvalidPosition(String)
+}
+
+impl fmt::Display for XBoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // these are the reply formats the protocol defines
+        match *self {
+            XBoardError::UnknownCommand(ref cmd) => write!(f, "Error (unknown command): {}", cmd),
+            XBoardError::IllegalMove(ref m) => write!(f, "Illegal move: {}", m),
+            XBoardError::InvalidArgument(ref cmd) => write!(f, "Error (invalid argument): {}", cmd),
+            XBoardError::InvalidPosition(_) => write!(f, "tellusererror Illegal position")
+        }
+    }
+}
+
+// stdin lines and finished searches are funnelled into one queue, so that
+// the driver can wait on both at once
+enum XBoardEvent {
+    Input(String),
+    EngineMove(Move)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct TimeControl {
+    moves_per_session: Option<u32>,
+    increment_ms: u32,
+    seconds_per_move: Option<u32>,
+    max_depth: Option<u8>,
+    engine_time_ms: u32,
+    opponent_time_ms: u32,
+    // since the clocks were last set by new, level or setboard, as the move
+    // numbers of a FEN say nothing about the time control
+    plies_played: u32
+}
+
+impl TimeControl {
+    // xboard's default of 40 moves in 5 minutes
+    fn new() -> TimeControl {
+        TimeControl {
+            moves_per_session: Some(40),
+            increment_ms: 0,
+            seconds_per_move: None,
+            max_depth: None,
+            engine_time_ms: 300000,
+            opponent_time_ms: 300000,
+            plies_played: 0
+        }
+    }
+
+    fn limits(&self, game: &Game) -> SearchLimits {
+        let mut limits = SearchLimits::new();
+        limits.depth = self.max_depth;
+
+        if let Some(seconds) = self.seconds_per_move {
+            limits.movetime = Some(seconds * 1000);
+            return limits;
+        }
+
+        let (wtime, btime) = match game.to_move {
+            Color::White => (self.engine_time_ms, self.opponent_time_ms),
+            Color::Black => (self.opponent_time_ms, self.engine_time_ms)
+        };
+
+        limits.wtime = Some(wtime);
+        limits.btime = Some(btime);
+        limits.winc = self.increment_ms;
+        limits.binc = self.increment_ms;
+
+        if let Some(mps) = self.moves_per_session {
+            // the engine is on move, so has made half of the moves played
+            let moves_played = self.plies_played / 2;
+            limits.movestogo = Some(mps - moves_played % mps);
+        }
+
+        limits
+    }
+}
+
+pub struct XBoard {
+    engine: Feldspar,
+    game: Game,
+    // the position before each move of the game, for undo and remove
+    undo_stack: Vec<Game>,
+    history: Vec<Hash>,
+    // None in force mode
+    engine_color: Option<Color>,
+    time_control: TimeControl,
+    thinking: bool,
+    // moves still to arrive from searches that were stopped with no move wanted
+    discarded_moves: usize
+}
+
+impl XBoard {
+    pub fn new(mut engine: Feldspar) -> XBoard {
+        engine.set_protocol(Protocol::XBoard);
+
+        XBoard {
+            engine: engine,
+            game: Game::starting_position(),
+            undo_stack: Vec::new(),
+            history: Vec::new(),
+            engine_color: Some(Color::Black),
+            time_control: TimeControl::new(),
+            thinking: false,
+            discarded_moves: 0
+        }
+    }
+
+    pub fn run(&mut self) {
+        let (sender, receiver) = channel();
+
+        let move_sender = sender.clone();
+        self.engine.report_moves_to(Box::new(move |m| {
+            let _ = move_sender.send(XBoardEvent::EngineMove(m));
+        }));
+
+        spawn_input_reader(sender);
+
+        for event in receiver.iter() {
+            match event {
+                XBoardEvent::Input(line) => {
+                    info!(target: INPUT_TARGET, "{}", line);
+
+                    match parse_xboard_command(&line, &self.game) {
+                        Ok(Some(XBoardCommand::Quit)) => break,
+                        Ok(Some(cmd)) => self.execute(cmd),
+                        Ok(None) => {},
+                        Err(e) => {
+                            warn!("{:?}", e);
+                            send!("{}", e);
+                        }
+                    }
+                },
+
+                XBoardEvent::EngineMove(m) => self.engine_moved(m)
+            }
+        }
+
+        self.abandon_search();
+        self.engine.quit();
+    }
+
+    fn execute(&mut self, cmd: XBoardCommand) {
+        match cmd {
+            XBoardCommand::XBoard => {},
+            XBoardCommand::Protover(_) => {
+                send!("feature done=0");
+                send!("feature myname=\"{}\" ping=1 setboard=1 usermove=1 time=1 memory=1 \
+                       draw=0 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 variants=\"normal\"",
+                      self.engine.name());
+                send!("feature done=1");
+            },
+            XBoardCommand::Accepted | XBoardCommand::Rejected => {},
+            XBoardCommand::New => {
+                self.abandon_search();
+                self.engine.reset();
+                self.set_position(Game::starting_position());
+                self.engine_color = Some(Color::Black);
+                self.time_control.seconds_per_move = None;
+                self.time_control.max_depth = None;
+            },
+            XBoardCommand::Force => {
+                self.abandon_search();
+                self.engine_color = None;
+            },
+            XBoardCommand::Go => {
+                self.engine_color = Some(self.game.to_move);
+                self.think_if_on_move();
+            },
+            XBoardCommand::PlayOther => {
+                self.engine_color = Some(!self.game.to_move);
+            },
+            XBoardCommand::UserMove(m) => {
+                // a move while we are thinking means the GUI thinks it's the user's turn
+                self.abandon_search();
+                self.play_move(m);
+                self.think_if_on_move();
+            },
+            XBoardCommand::SetBoard(game) => {
+                self.abandon_search();
+                self.set_position(game);
+            },
+            XBoardCommand::Level { moves_per_session, base_ms, increment_ms } => {
+                self.time_control.moves_per_session = moves_per_session;
+                self.time_control.increment_ms = increment_ms;
+                self.time_control.seconds_per_move = None;
+                self.time_control.engine_time_ms = base_ms;
+                self.time_control.opponent_time_ms = base_ms;
+                self.time_control.plies_played = 0;
+            },
+            XBoardCommand::St(seconds)   => self.time_control.seconds_per_move = Some(seconds),
+            XBoardCommand::Sd(depth)     => self.time_control.max_depth = Some(depth),
+            XBoardCommand::Time(cs)      => self.time_control.engine_time_ms = cs.saturating_mul(10),
+            XBoardCommand::Otim(cs)      => self.time_control.opponent_time_ms = cs.saturating_mul(10),
+            XBoardCommand::Undo => {
+                self.abandon_search();
+                self.take_back(1);
+            },
+            XBoardCommand::Remove => {
+                self.abandon_search();
+                self.take_back(2);
+            },
+            XBoardCommand::Post   => self.engine.set_post(true),
+            XBoardCommand::NoPost => self.engine.set_post(false),
+            XBoardCommand::Result(result) => {
+                debug!("game ended: {}", result);
+                self.abandon_search();
+                self.engine_color = None;
+            },
+            XBoardCommand::Ping(n) => {
+                self.engine.wait_until_ready();
+                send!("pong {}", n);
+            },
+            XBoardCommand::MoveNow => {
+                // the move arrives through the reporter once the search has stopped
+                if self.thinking {
+                    self.engine.stop();
+                }
+            },
+            XBoardCommand::Memory(mb) => {
+                // held to the same range as the Hash option
+                if let Err(e) = self.engine.apply_option("Hash", &mb.to_string()) {
+                    warn!("{:?}", e);
+                    send!("Error ({}): memory {}", e, mb);
+                }
+            },
+            XBoardCommand::Ignored => {},
+            XBoardCommand::Quit => {}
+        }
+    }
+
+    fn engine_moved(&mut self, m: Move) {
+        if self.discarded_moves > 0 {
+            self.discarded_moves -= 1;
+            return;
+        }
+
+        self.thinking = false;
+
+        if m.is_null() {
+            return;
+        }
+
+        send!("move {}", m.to_uci_str());
+        self.play_move(m);
+    }
+
+    // stops the current search, the move it produces is thrown away
+    fn abandon_search(&mut self) {
+        if self.thinking {
+            self.engine.stop();
+            self.thinking = false;
+            self.discarded_moves += 1;
+        }
+    }
+
+    fn think_if_on_move(&mut self) {
+        if self.thinking || self.engine_color != Some(self.game.to_move) || self.game_result().is_some() {
+            return;
+        }
+
+        let limits = self.time_control.limits(&self.game);
+        self.thinking = true;
+        self.engine.find_best_move(limits);
+    }
+
+    fn set_position(&mut self, game: Game) {
+        self.game = game;
+        self.undo_stack.clear();
+        self.history.clear();
+        self.time_control.plies_played = 0;
+        self.engine.replace_game(self.game, self.history.clone());
+    }
+
+    fn play_move(&mut self, m: Move) {
+        self.undo_stack.push(self.game);
+        self.game.make_move(m);
+        self.history.push(self.game.hash);
+        self.time_control.plies_played += 1;
+        self.engine.replace_game(self.game, self.history.clone());
+
+        if let Some(end) = self.game_result() {
+            send!("{} {{{}}}", end.score_str(), end.reason());
+            self.engine_color = None;
+        }
+    }
+
+    fn take_back(&mut self, plies: usize) {
+        for _ in 0 .. plies {
+            match self.undo_stack.pop() {
+                Some(previous) => {
+                    self.game = previous;
+                    self.history.pop();
+                    self.time_control.plies_played = self.time_control.plies_played.saturating_sub(1);
+                },
+                None => break
+            }
+        }
+
+        self.engine.replace_game(self.game, self.history.clone());
+    }
+
+    fn game_result(&self) -> Option<GameEnd> {
+        self.game.game_end(&self.history)
+    }
+}
+
+fn spawn_input_reader(sender: Sender<XBoardEvent>) {
+    thread::Builder::new()
+        .name("input".to_string())
+        .spawn(move || {
+            let stdin = stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(l) => if sender.send(XBoardEvent::Input(l)).is_err() { return; },
+                    Err(e) => {
+                        error!("failed to read from gui/server: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            // treat a closed stdin like `quit`
+            let _ = sender.send(XBoardEvent::Input("quit".to_string()));
+        })
+        .expect("failed to spawn input thread");
+}
+
+pub fn parse_xboard_command(line: &str, game: &Game) -> Result<Option<XBoardCommand>, XBoardError> {
+    let mut args = line.split_whitespace();
+
+    let word = match args.next() {
+        Some(w) => w,
+        None => return Ok(None)
+    };
+
+    let invalid = || XBoardError::InvalidArgument(line.to_string());
+
+    let cmd = match word {
+        "xboard"    => XBoardCommand::XBoard,
+        "protover"  => XBoardCommand::Protover(next_number(&mut args).ok_or_else(invalid)?),
+        "accepted"  => XBoardCommand::Accepted,
+        "rejected"  => XBoardCommand::Rejected,
+        "new"       => XBoardCommand::New,
+        "force"     => XBoardCommand::Force,
+        "go"        => XBoardCommand::Go,
+        "playother" => XBoardCommand::PlayOther,
+        "usermove"  => match args.next() {
+            Some(m) => parse_move(m, game)?,
+            None => return Err(invalid())
+        },
+        "setboard"  => {
+            let fen = args.collect::<Vec<&str>>().join(" ");
+            match Game::from_fen_str(&fen) {
+                Some(g) => XBoardCommand::SetBoard(g),
+                None => return Err(XBoardError::InvalidPosition(fen))
+            }
+        },
+        "level"     => parse_level(&mut args).ok_or_else(invalid)?,
+        "st"        => XBoardCommand::St(next_number(&mut args).ok_or_else(invalid)?),
+        "sd"        => XBoardCommand::Sd(next_number(&mut args).ok_or_else(invalid)?),
+        "time"      => XBoardCommand::Time(next_number(&mut args).ok_or_else(invalid)?),
+        "otim"      => XBoardCommand::Otim(next_number(&mut args).ok_or_else(invalid)?),
+        "undo"      => XBoardCommand::Undo,
+        "remove"    => XBoardCommand::Remove,
+        "post"      => XBoardCommand::Post,
+        "nopost"    => XBoardCommand::NoPost,
+        "result"    => XBoardCommand::Result(args.collect::<Vec<&str>>().join(" ")),
+        "ping"      => XBoardCommand::Ping(args.next().unwrap_or("").to_string()),
+        "?"         => XBoardCommand::MoveNow,
+        "memory"    => XBoardCommand::Memory(next_number(&mut args).ok_or_else(invalid)?),
+        "quit"      => XBoardCommand::Quit,
+        "random" | "hard" | "easy" | "computer" | "name" | "rating" | "ics" |
+        "cores" | "draw" | "hint" | "bk" | "variant" | "white" | "black" => XBoardCommand::Ignored,
+        // without usermove=1 an older interface sends bare moves
+        _ => match move_from_algebraic(game, word.to_string()) {
+            Some(m) => XBoardCommand::UserMove(m),
+            None => return Err(XBoardError::UnknownCommand(word.to_string()))
+        }
+    };
+
+    Ok(Some(cmd))
+}
+
+fn parse_move(move_str: &str, game: &Game) -> Result<XBoardCommand, XBoardError> {
+    match move_from_algebraic(game, move_str.to_string()) {
+        Some(m) => Ok(XBoardCommand::UserMove(m)),
+        None => Err(XBoardError::IllegalMove(move_str.to_string()))
+    }
+}
+
+// level MPS BASE INC, where BASE is minutes or minutes:seconds and INC is in seconds
+fn parse_level<'a>(args: &mut SplitWhitespace<'a>) -> Option<XBoardCommand> {
+    let mps: u32 = next_number(args)?;
+
+    let base = args.next()?;
+    let base_seconds = match base.find(':') {
+        Some(idx) => {
+            let minutes: u32 = base[..idx].parse().ok()?;
+            let seconds: u32 = base[idx + 1 ..].parse().ok()?;
+            minutes.checked_mul(60)?.checked_add(seconds)?
+        },
+        None => base.parse::<u32>().ok()?.checked_mul(60)?
+    };
+
+    let increment: f64 = next_number(args)?;
+    if !(increment >= 0.0) {
+        return None;
+    }
+
+    Some(XBoardCommand::Level {
+        moves_per_session: if mps > 0 { Some(mps) } else { None },
+        base_ms: base_seconds.checked_mul(1000)?,
+        increment_ms: (increment * 1000.0).min(u32::max_value() as f64) as u32
+    })
+}
+
+fn next_number<'a, T: FromStr>(args: &mut SplitWhitespace<'a>) -> Option<T> {
+    args.next().and_then(|x| x.parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use xboard::*;
+
+    #[test]
+    fn commands() {
+        let game = Game::starting_position();
+
+        assert_eq!(parse_xboard_command("", &game), Ok(None));
+        assert_eq!(parse_xboard_command("protover 2", &game), Ok(Some(XBoardCommand::Protover(2))));
+        assert_eq!(parse_xboard_command("sd 8", &game), Ok(Some(XBoardCommand::Sd(8))));
+        assert_eq!(parse_xboard_command("time 12345", &game), Ok(Some(XBoardCommand::Time(12345))));
+        assert_eq!(parse_xboard_command("level 40 5 0", &game),
+                   Ok(Some(XBoardCommand::Level { moves_per_session: Some(40), base_ms: 300000, increment_ms: 0 })));
+        assert_eq!(parse_xboard_command("level 0 2:30 1.5", &game),
+                   Ok(Some(XBoardCommand::Level { moves_per_session: None, base_ms: 150000, increment_ms: 1500 })));
+
+        match parse_xboard_command("usermove e2e4", &game) {
+            Ok(Some(XBoardCommand::UserMove(m))) => assert_eq!(m.to_uci_str(), "e2e4"),
+            x => panic!("unexpected parse result: {:?}", x)
+        }
+
+        match parse_xboard_command("g1f3", &game) {
+            Ok(Some(XBoardCommand::UserMove(m))) => assert_eq!(m.to_uci_str(), "g1f3"),
+            x => panic!("unexpected parse result: {:?}", x)
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let game = Game::starting_position();
+
+        for line in ["foo", "usermove", "usermove e2e5", "protover", "level 40", "level 40 five 0",
+                     "level 40 5:xx 0", "level 40 5 -1", "st soon", "sd -3", "time",
+                     "setboard", "setboard 8/8/8/8/8/8/8/8 w - - 0 1"].iter() {
+            assert!(parse_xboard_command(line, &game).is_err(), "'{}' should not parse", line);
+        }
+
+        assert_eq!(parse_xboard_command("usermove e2e5", &game),
+                   Err(XBoardError::IllegalMove("e2e5".to_string())));
+    }
+
+    #[test]
+    fn time_control() {
+        let game = Game::starting_position();
+        let mut tc = TimeControl::new();
+
+        let limits = tc.limits(&game);
+        assert_eq!(limits.wtime, Some(300000));
+        assert_eq!(limits.movestogo, Some(40));
+
+        // the session counts moves played since the clocks were set, not the FEN's move number
+        let midgame = Game::from_fen_str("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 30").unwrap();
+        assert_eq!(tc.limits(&midgame).movestogo, Some(40));
+        tc.plies_played = 2 * 39;
+        assert_eq!(tc.limits(&midgame).movestogo, Some(1));
+        tc.plies_played = 2 * 40 + 1;
+        assert_eq!(tc.limits(&midgame).movestogo, Some(40));
+
+        tc.seconds_per_move = Some(3);
+        tc.max_depth = Some(5);
+        let limits = tc.limits(&game);
+        assert_eq!(limits.movetime, Some(3000));
+        assert_eq!(limits.depth, Some(5));
+    }
+
+    #[test]
+    fn session_moves() {
+        init_zobrist_hashing();
+
+        let mut xboard = XBoard::new(Feldspar::new());
+        let game = Game::from_fen_str("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 30").unwrap();
+
+        xboard.execute(XBoardCommand::SetBoard(game));
+        xboard.execute(XBoardCommand::Force);
+        for m in ["f1c4", "g8f6", "d2d3"].iter() {
+            let cmd = parse_xboard_command(m, &xboard.game).unwrap().unwrap();
+            xboard.execute(cmd);
+        }
+        assert_eq!(xboard.time_control.plies_played, 3);
+
+        xboard.execute(XBoardCommand::Remove);
+        assert_eq!(xboard.time_control.plies_played, 1);
+
+        xboard.execute(XBoardCommand::Level { moves_per_session: Some(40), base_ms: 60000, increment_ms: 0 });
+        assert_eq!(xboard.time_control.plies_played, 0);
+
+        // out of the Hash option's range, so refused rather than allocated
+        xboard.execute(XBoardCommand::Memory(u32::max_value()));
+
+        xboard.engine.quit();
+    }
+}
@@ -9,6 +9,10 @@ use eval::*;
 
 use rand::Rng;
 
+use std::cmp::{max, min};
+use std::mem::size_of;
+use std::sync::Once;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Hash(u64);
 
@@ -76,24 +80,46 @@ impl Hash {
     }
 }
 
+static ZOBRIST_INIT: Once = Once::new();
+
+// Keys come from a fixed seed, so that hashes, and with them the search's
+// node counts, are the same from run to run.
+const ZOBRIST_SEED: u64 = 0x4665_6c64_7370_6172;
+
+// xorshift64*, which is enough for hash keys and doesn't depend on how a
+// given version of the rand crate seeds its generators
+struct KeyGenerator(u64);
+
+impl KeyGenerator {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+// safe to call more than once, the keys are only generated the first time
 pub fn init_zobrist_hashing() {
-    unsafe {
+    ZOBRIST_INIT.call_once(|| unsafe {
+        let mut rng = KeyGenerator(ZOBRIST_SEED);
+
         for i in 0 .. 12 {
             for j in 0 .. 64 {
-                piece_keys[i][j] = rand::random::<u64>();
+                piece_keys[i][j] = rng.next();
             }
         }
 
-        black_to_move_key = rand::random::<u64>();
+        black_to_move_key = rng.next();
 
         for i in 0 .. 16 {
-            castle_keys[i] = rand::random::<u64>();
+            castle_keys[i] = rng.next();
         }
 
         for i in 0 .. 8 {
-            ep_keys[i] = rand::random::<u64>();
+            ep_keys[i] = rng.next();
         }
-    }
+    });
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -205,6 +231,11 @@ impl TranspositionTable {
         }
     }
 
+    pub fn with_size_mb(megabytes: usize) -> TranspositionTable {
+        let count = megabytes * 1024 * 1024 / size_of::<TableEntry>();
+        TranspositionTable::new(max(count, 1))
+    }
+
     pub fn probe(&self, hash: Hash) -> Option<EntryData> {
         let idx = (hash.unwrap() % self.entries.len() as u64) as usize;
 
@@ -225,6 +256,16 @@ impl TranspositionTable {
         }
     }
 
+    // permill of entries in use, sampled from the start of the table
+    pub fn hashfull(&self) -> usize {
+        let sample_size = min(self.entries.len(), 1000);
+        let used = self.entries[..sample_size].iter()
+            .filter(|x| x.entry != EntryData::empty())
+            .count();
+
+        used * 1000 / sample_size
+    }
+
     pub fn reset(&mut self) {
         for x in self.entries.iter_mut() {
             x.key = Key::empty();